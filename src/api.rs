@@ -0,0 +1,950 @@
+use std::net::SocketAddr;
+
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, Response};
+use hyper::body::{Bytes, Incoming};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use tracing::{error, instrument};
+
+use crate::{
+    db_connect, get_query_pairs, DbConnectError, load_vehicles_where, lock_coupling_company, lock_vehicle_company,
+    set_change_context, store_coupling, store_vehicle, trash_vehicle, ExportedVehicle, StoreCouplingError,
+    TrashVehicleError,
+};
+use crate::auth::{self, Principal};
+use crate::config;
+use crate::natural_sort;
+use crate::value_sets;
+
+
+/// The largest number of vehicles that may be requested per page.
+const MAX_PER_PAGE: i64 = 1000;
+
+/// Query parameters by which the vehicle list can be filtered and the columns they compare.
+const VEHICLE_FILTERS: [(&str, &str); 6] = [
+    ("company", "company"),
+    ("number", "veh_number"),
+    ("type_code", "type_code"),
+    ("vehicle_class", "veh_class"),
+    ("manufacturer", "manufacturer"),
+    ("depot", "depot"),
+];
+
+
+/// A vehicle as returned by the API.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct ApiVehicle {
+    pub id: i64,
+    pub company: String,
+    #[serde(flatten)] pub vehicle: ExportedVehicle,
+}
+
+/// A vehicle as accepted by the API. `id` and `fixed_coupling` are ignored if present.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct ApiVehicleInput {
+    pub company: String,
+    #[serde(flatten)] pub vehicle: ExportedVehicle,
+}
+
+/// The change to apply to a vehicle received via the API.
+#[derive(Clone, Debug, PartialEq)]
+enum ApiVehicleChange {
+    /// Replaces the whole vehicle.
+    Replace(Box<ApiVehicleInput>),
+
+    /// Applies a JSON merge patch to the current state of the vehicle.
+    Patch(serde_json::Value),
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ApiVehicleList {
+    pub vehicles: Vec<ApiVehicle>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+/// A vehicle within a coupling as returned by the API.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct ApiCouplingVehicle {
+    pub id: i64,
+    pub number: String,
+}
+
+/// A coupling as returned by the API; the vehicles are in coupling order.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct ApiCoupling {
+    pub id: i64,
+    pub company: String,
+    pub vehicles: Vec<ApiCouplingVehicle>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ApiCouplingList {
+    pub couplings: Vec<ApiCoupling>,
+}
+
+/// A reference to a vehicle: either its ID, a `[company, number]` pair or a
+/// `{"company": ..., "number": ...}` object.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+enum ApiVehicleRef {
+    Id(i64),
+    Pair(String, String),
+    Named { company: String, number: String },
+}
+
+/// A coupling as accepted by the API.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+struct ApiCouplingInput {
+    pub vehicles: Vec<ApiVehicleRef>,
+}
+
+
+pub(crate) fn api_error(status: u16, message: &str) -> Response<Full<Bytes>> {
+    api_error_with(status, message, serde_json::Map::new())
+}
+
+/// Returns an API error whose error object contains additional members.
+fn api_error_with(status: u16, message: &str, details: serde_json::Map<String, serde_json::Value>) -> Response<Full<Bytes>> {
+    let mut error_object = details;
+    error_object.insert("status".to_owned(), status.into());
+    error_object.insert("message".to_owned(), message.into());
+    let body = serde_json::json!({
+        "error": error_object,
+    });
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap_or_else(|_| crate::return_500())
+}
+
+/// Returns the API error for a failure to obtain a database connection.
+fn api_db_unavailable(e: DbConnectError) -> Response<Full<Bytes>> {
+    match e {
+        DbConnectError::PoolExhausted => {
+            let mut response = api_error(503, "service unavailable; please retry later");
+            response.headers_mut().insert("Retry-After", DbConnectError::RETRY_AFTER_S.into());
+            response
+        },
+        DbConnectError::Failed => api_error(500, "internal server error"),
+    }
+}
+
+fn api_error_405(method: &Method, allowed_methods: &[Method]) -> Response<Full<Bytes>> {
+    let allowed_methods: Vec<&str> = allowed_methods.iter().map(|m| m.as_str()).collect();
+    let allowed_methods_string = allowed_methods.join(", ");
+    let mut response = api_error(405, &format!("unsupported method {}; allowed: {}", method, allowed_methods_string));
+    if let Ok(allow_value) = allowed_methods_string.parse() {
+        response.headers_mut().insert("Allow", allow_value);
+    }
+    response
+}
+
+fn api_json<T: Serialize>(status: u16, value: &T) -> Response<Full<Bytes>> {
+    let body = match serde_json::to_string_pretty(value) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("failed to serialize API response: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap_or_else(|_| api_error(500, "internal server error"))
+}
+
+fn api_no_content() -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(204)
+        .body(Full::new(Bytes::new()))
+        .unwrap_or_else(|_| api_error(500, "internal server error"))
+}
+
+async fn read_json_body<T: DeserializeOwned>(request: Request<Incoming>) -> Result<T, Response<Full<Bytes>>> {
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return Err(api_error(500, "internal server error"));
+        },
+    };
+    serde_json::from_slice(&request_bytes)
+        .map_err(|e| api_error(400, &format!("invalid request body: {}", e)))
+}
+
+/// Applies a JSON merge patch (RFC 7386) to a value.
+fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    if let serde_json::Value::Object(patch_map) = patch {
+        if !target.is_object() {
+            *target = serde_json::Value::Object(serde_json::Map::new());
+        }
+        let target_map = target.as_object_mut().expect("target is an object");
+        for (key, value) in patch_map {
+            if value.is_null() {
+                target_map.remove(key);
+            } else {
+                let target_value = target_map.entry(key.clone())
+                    .or_insert(serde_json::Value::Null);
+                merge_patch(target_value, value);
+            }
+        }
+    } else {
+        *target = patch.clone();
+    }
+}
+
+fn parse_query_i64(query_pairs: &[(String, Option<String>)], key: &str) -> Result<Option<i64>, String> {
+    let value_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == key)
+        .flat_map(|(_k, v)| v)
+        .last();
+    match value_opt {
+        Some(v) => match v.parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(format!("invalid value for parameter '{}'", key)),
+        },
+        None => Ok(None),
+    }
+}
+
+async fn load_api_vehicle<C: deadpool_postgres::GenericClient>(db_conn: &C, id: i64) -> Result<Option<ApiVehicle>, Response<Full<Bytes>>> {
+    let mut vehicles = match load_vehicles_where(db_conn, "b.id = $1", &id).await {
+        Some(v) => v,
+        None => return Err(api_error(500, "internal server error")),
+    };
+    Ok(vehicles.pop().map(|(id, company, vehicle)| ApiVehicle { id, company, vehicle }))
+}
+
+
+#[instrument(skip_all)]
+async fn handle_vehicle_list(principal: Option<&Principal>, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return api_error(400, "invalid UTF-8 in query"),
+    };
+
+    let page = match parse_query_i64(&query_pairs, "page") {
+        Ok(p) => p.unwrap_or(0),
+        Err(e) => return api_error(400, &e),
+    };
+    if page < 0 {
+        return api_error(400, "'page' must be >= 0");
+    }
+    let per_page = match parse_query_i64(&query_pairs, "per_page") {
+        Ok(pp) => pp.unwrap_or_else(|| config::current().vehicles_per_page),
+        Err(e) => return api_error(400, &e),
+    };
+    if !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return api_error(400, &format!("'per_page' must be between 1 and {}", MAX_PER_PAGE));
+    }
+    let offset = page * per_page;
+
+    let view_scope = auth::view_scope(principal);
+    let mut conditions = vec!["deleted_at IS NULL".to_owned()];
+    let mut filter_values = Vec::new();
+    for (param, column) in VEHICLE_FILTERS {
+        let value_opt = query_pairs.iter()
+            .filter(|(k, _v)| k == param)
+            .flat_map(|(_k, v)| v)
+            .last();
+        if let Some(value) = value_opt {
+            if param == "company" && !view_scope.includes(value) {
+                return api_error(403, &format!("you may not view vehicles of company {:?}", value));
+            }
+            filter_values.push(value.clone());
+            conditions.push(format!("{} = ${}", column, filter_values.len()));
+        }
+    }
+    conditions.push(view_scope.sql_condition("company", &mut filter_values));
+    let where_clause = format!("WHERE {}", conditions.join(" AND "));
+    let mut query_params: Vec<&(dyn ToSql + Sync)> = filter_values.iter()
+        .map(|v| v as &(dyn ToSql + Sync))
+        .collect();
+
+    let db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return api_db_unavailable(e),
+    };
+
+    let count_query = format!("SELECT COUNT(*) FROM bimdb.bims {}", where_clause);
+    let total: i64 = match db_conn.query_one(&count_query, &query_params).await {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            error!("failed to count vehicles: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+
+    query_params.push(&per_page);
+    query_params.push(&offset);
+    let number_order = natural_sort::sql_order_keys("veh_number");
+    let id_query = format!(
+        "
+            SELECT id
+            FROM bimdb.bims
+            {}
+            ORDER BY company, {}, {}, id
+            LIMIT ${} OFFSET ${}
+        ",
+        where_clause,
+        number_order[0], number_order[1],
+        query_params.len() - 1,
+        query_params.len(),
+    );
+    let ids: Vec<i64> = match db_conn.query(&id_query, &query_params).await {
+        Ok(rows) => rows.iter().map(|r| r.get(0)).collect(),
+        Err(e) => {
+            error!("failed to obtain vehicle IDs: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+
+    let mut vehicles = match load_vehicles_where(&db_conn, "b.id = ANY($1)", &ids).await {
+        Some(v) => v,
+        None => return api_error(500, "internal server error"),
+    };
+    // restore the order of the ID query
+    vehicles.sort_by_key(|(id, _company, _vehicle)| ids.iter().position(|i| i == id));
+
+    let list = ApiVehicleList {
+        vehicles: vehicles.into_iter()
+            .map(|(id, company, vehicle)| ApiVehicle { id, company, vehicle })
+            .collect(),
+        page,
+        per_page,
+        total,
+    };
+    api_json(200, &list)
+}
+
+/// Validates and stores a vehicle received via the API; `id` is `None` when creating a vehicle.
+///
+/// Patches are applied to the vehicle as locked within the storing transaction so that concurrent
+/// changes are not lost.
+async fn store_api_vehicle(remote_addr: SocketAddr, principal: Option<&Principal>, id: Option<i64>, change: ApiVehicleChange) -> Response<Full<Bytes>> {
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return api_db_unavailable(e),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if let Err(e) = set_change_context(&db_txn, principal, Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return api_error(500, "internal server error");
+    }
+
+    let current = match id {
+        Some(edit_id) => {
+            let old_company = match lock_vehicle_company(&db_txn, edit_id).await {
+                Ok(Some(oc)) => oc,
+                Ok(None) => return api_error(404, "vehicle not found"),
+                Err(e) => {
+                    error!("failed to obtain company of vehicle {}: {}", edit_id, e);
+                    return api_error(500, "internal server error");
+                },
+            };
+            if !auth::may_edit(principal, &old_company) {
+                return api_error(403, &format!("you may not edit vehicles of company {:?}", old_company));
+            }
+            match load_api_vehicle(&db_txn, edit_id).await {
+                Ok(Some(v)) => Some(v),
+                Ok(None) => return api_error(404, "vehicle not found"),
+                Err(r) => return r,
+            }
+        },
+        None => None,
+    };
+
+    let mut input = match change {
+        ApiVehicleChange::Replace(i) => *i,
+        ApiVehicleChange::Patch(patch) => {
+            let Some(current) = &current else {
+                return api_error(400, "only existing vehicles can be patched");
+            };
+            let mut current_value = match serde_json::to_value(current) {
+                Ok(cv) => cv,
+                Err(e) => {
+                    error!("failed to convert vehicle {} to JSON: {}", current.id, e);
+                    return api_error(500, "internal server error");
+                },
+            };
+            merge_patch(&mut current_value, &patch);
+            match serde_json::from_value(current_value) {
+                Ok(i) => i,
+                Err(e) => return api_error(400, &format!("patched vehicle is invalid: {}", e)),
+            }
+        },
+    };
+
+    if let Err(e) = ExportedVehicle::validate_company(&input.company) {
+        return api_error(400, &e);
+    }
+    if !auth::may_edit(principal, &input.company) {
+        return api_error(403, &format!("you may not edit vehicles of company {:?}", input.company));
+    }
+    let value_sets = match value_sets::load_value_sets(&db_txn).await {
+        Some(vs) => vs,
+        None => return api_error(500, "internal server error"),
+    };
+    let stored = current.as_ref()
+        .filter(|c| c.company == input.company)
+        .map(|c| &c.vehicle);
+    if let Err(e) = input.vehicle.validate(&input.company, &value_sets, stored) {
+        return api_error(400, &e);
+    }
+
+    let bim_id = match store_vehicle(&db_txn, id, &input.company, &input.vehicle).await {
+        Ok(Some(bi)) => bi,
+        Ok(None) => return api_error(404, "vehicle not found"),
+        Err(e) => {
+            if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                return api_error(409, "a vehicle with this number already exists in this company");
+            }
+            error!("failed to store vehicle (ID {:?}): {}", id, e);
+            return api_error(500, "internal server error");
+        },
+    };
+
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit vehicle transaction: {}", e);
+        return api_error(500, "internal server error");
+    }
+
+    let vehicle = match load_api_vehicle(&db_conn, bim_id).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return api_error(404, "vehicle not found"),
+        Err(r) => return r,
+    };
+    if id.is_none() {
+        let base_path = &config::current()
+            .http.base_path;
+        let mut response = api_json(201, &vehicle);
+        if let Ok(location) = format!("{}/api/v1/vehicles/{}", base_path, bim_id).parse() {
+            response.headers_mut().insert("Location", location);
+        }
+        response
+    } else {
+        api_json(200, &vehicle)
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle_vehicle_patch(remote_addr: SocketAddr, principal: Option<&Principal>, request: Request<Incoming>, id: i64) -> Response<Full<Bytes>> {
+    let patch: serde_json::Value = match read_json_body(request).await {
+        Ok(p) => p,
+        Err(r) => return r,
+    };
+    if !patch.is_object() {
+        return api_error(400, "patch must be a JSON object");
+    }
+
+    store_api_vehicle(remote_addr, principal, Some(id), ApiVehicleChange::Patch(patch)).await
+}
+
+#[instrument(skip_all)]
+async fn handle_vehicle_delete(remote_addr: SocketAddr, principal: Option<&Principal>, id: i64) -> Response<Full<Bytes>> {
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return api_db_unavailable(e),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if let Err(e) = set_change_context(&db_txn, principal, Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return api_error(500, "internal server error");
+    }
+    let company = match lock_vehicle_company(&db_txn, id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return api_error(404, "vehicle not found"),
+        Err(e) => {
+            error!("failed to obtain company of vehicle {}: {}", id, e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if !auth::may_edit(principal, &company) {
+        return api_error(403, &format!("you may not delete vehicles of company {:?}", company));
+    }
+    match trash_vehicle(&db_txn, id, principal).await {
+        Ok(()) => {},
+        Err(TrashVehicleError::Coupled(coupling_id)) => {
+            return api_error(409, &format!("vehicle is part of coupling {}", coupling_id));
+        },
+        Err(TrashVehicleError::Database(e)) => {
+            error!("failed to delete vehicle {}: {}", id, e);
+            return api_error(500, "internal server error");
+        },
+    }
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit vehicle deletion transaction: {}", e);
+        return api_error(500, "internal server error");
+    }
+    api_no_content()
+}
+
+async fn handle_vehicles(remote_addr: SocketAddr, principal: Option<&Principal>, request: Request<Incoming>, id_opt: Option<i64>) -> Response<Full<Bytes>> {
+    let Some(id) = id_opt else {
+        return if request.method() == Method::GET {
+            handle_vehicle_list(principal, request).await
+        } else if request.method() == Method::POST {
+            match read_json_body(request).await {
+                Ok(input) => store_api_vehicle(remote_addr, principal, None, ApiVehicleChange::Replace(Box::new(input))).await,
+                Err(r) => r,
+            }
+        } else {
+            api_error_405(request.method(), &[Method::GET, Method::POST])
+        };
+    };
+
+    if request.method() == Method::GET {
+        let db_conn = match db_connect().await {
+            Ok(dbc) => dbc,
+            Err(e) => return api_db_unavailable(e),
+        };
+        match load_api_vehicle(&db_conn, id).await {
+            Ok(Some(v)) if auth::view_scope(principal).includes(&v.company) => api_json(200, &v),
+            Ok(_) => api_error(404, "vehicle not found"),
+            Err(r) => r,
+        }
+    } else if request.method() == Method::PUT {
+        match read_json_body(request).await {
+            Ok(input) => store_api_vehicle(remote_addr, principal, Some(id), ApiVehicleChange::Replace(Box::new(input))).await,
+            Err(r) => r,
+        }
+    } else if request.method() == Method::PATCH {
+        handle_vehicle_patch(remote_addr, principal, request, id).await
+    } else if request.method() == Method::DELETE {
+        handle_vehicle_delete(remote_addr, principal, id).await
+    } else {
+        api_error_405(request.method(), &[Method::GET, Method::PUT, Method::PATCH, Method::DELETE])
+    }
+}
+
+
+async fn load_api_couplings<C: deadpool_postgres::GenericClient>(db_conn: &C, condition: &str, params: &[&(dyn ToSql + Sync)]) -> Option<Vec<ApiCoupling>> {
+    let query = format!(
+        "
+            SELECT cb.coupling_id, b.id, b.company, b.veh_number
+            FROM bimdb.coupling_bims cb
+            INNER JOIN bimdb.bims b ON b.id = cb.bim_id
+            WHERE {}
+            ORDER BY cb.coupling_id, cb.position
+        ",
+        condition,
+    );
+    let rows = match db_conn.query(&query, params).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to obtain couplings: {}", e);
+            return None;
+        },
+    };
+
+    let mut couplings: Vec<ApiCoupling> = Vec::new();
+    for row in rows {
+        let coupling_id: i64 = row.get(0);
+        let vehicle = ApiCouplingVehicle {
+            id: row.get(1),
+            number: row.get(3),
+        };
+        match couplings.last_mut() {
+            Some(coupling) if coupling.id == coupling_id => coupling.vehicles.push(vehicle),
+            _ => couplings.push(ApiCoupling {
+                id: coupling_id,
+                company: row.get(2),
+                vehicles: vec![vehicle],
+            }),
+        }
+    }
+    Some(couplings)
+}
+
+async fn load_api_coupling<C: deadpool_postgres::GenericClient>(db_conn: &C, id: i64) -> Result<Option<ApiCoupling>, Response<Full<Bytes>>> {
+    match load_api_couplings(db_conn, "cb.coupling_id = $1", &[&id]).await {
+        Some(mut couplings) => Ok(couplings.pop()),
+        None => Err(api_error(500, "internal server error")),
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle_coupling_list(principal: Option<&Principal>, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return api_error(400, "invalid UTF-8 in query"),
+    };
+    let company_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == "company")
+        .flat_map(|(_k, v)| v)
+        .last();
+    let view_scope = auth::view_scope(principal);
+    if let Some(company) = company_opt {
+        if !view_scope.includes(company) {
+            return api_error(403, &format!("you may not view couplings of company {:?}", company));
+        }
+    }
+
+    let db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return api_db_unavailable(e),
+    };
+    // all vehicles of a coupling belong to the same company
+    let mut params = Vec::new();
+    let mut conditions = vec![view_scope.sql_condition("b.company", &mut params)];
+    if let Some(company) = company_opt {
+        params.push(company.clone());
+        conditions.push(format!("b.company = ${}", params.len()));
+    }
+    let param_refs: Vec<&(dyn ToSql + Sync)> = params.iter()
+        .map(|p| p as &(dyn ToSql + Sync))
+        .collect();
+    let couplings_opt = load_api_couplings(&db_conn, &conditions.join(" AND "), &param_refs).await;
+    match couplings_opt {
+        Some(couplings) => api_json(200, &ApiCouplingList { couplings }),
+        None => api_error(500, "internal server error"),
+    }
+}
+
+/// Resolves the vehicle references and stores a coupling received via the API; `id` is `None` when
+/// creating a coupling.
+async fn store_api_coupling(remote_addr: SocketAddr, principal: Option<&Principal>, id: Option<i64>, input: ApiCouplingInput) -> Response<Full<Bytes>> {
+    if input.vehicles.len() < 2 {
+        return api_error(400, "a coupling must contain at least two vehicles");
+    }
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return api_db_unavailable(e),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if let Err(e) = set_change_context(&db_txn, principal, Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return api_error(500, "internal server error");
+    }
+
+    let mut vehicle_ids = Vec::with_capacity(input.vehicles.len());
+    for vehicle_ref in &input.vehicles {
+        let (company, number) = match vehicle_ref {
+            ApiVehicleRef::Id(vehicle_id) => {
+                vehicle_ids.push(*vehicle_id);
+                continue;
+            },
+            ApiVehicleRef::Pair(company, number) => (company, number),
+            ApiVehicleRef::Named { company, number } => (company, number),
+        };
+        let row_res = db_txn.query_opt(
+            "SELECT id FROM bimdb.bims WHERE company = $1 AND veh_number = $2 AND deleted_at IS NULL",
+            &[company, number],
+        ).await;
+        match row_res {
+            Ok(Some(row)) => vehicle_ids.push(row.get(0)),
+            Ok(None) => return api_error(400, &format!("unknown vehicle {:?} of company {:?}", number, company)),
+            Err(e) => {
+                error!("error querying ID of bim {:?} of company {:?}: {}", number, company, e);
+                return api_error(500, "internal server error");
+            },
+        }
+    }
+
+    if let Some(edit_id) = id {
+        let old_company_opt = match lock_coupling_company(&db_txn, edit_id).await {
+            Ok(Some(oc)) => oc,
+            Ok(None) => return api_error(404, "coupling not found"),
+            Err(e) => {
+                error!("failed to obtain company of coupling {}: {}", edit_id, e);
+                return api_error(500, "internal server error");
+            },
+        };
+        if let Some(old_company) = old_company_opt {
+            if !auth::may_edit(principal, &old_company) {
+                return api_error(403, &format!("you may not edit couplings of company {:?}", old_company));
+            }
+        }
+    }
+
+    // store_coupling ensures that the other vehicles belong to the same company
+    let company_opt: Option<String> = match db_txn.query_opt("SELECT company FROM bimdb.bims WHERE id = $1 AND deleted_at IS NULL", &[&vehicle_ids[0]]).await {
+        Ok(row_opt) => row_opt.map(|row| row.get(0)),
+        Err(e) => {
+            error!("failed to obtain company of vehicle {}: {}", vehicle_ids[0], e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if let Some(company) = company_opt {
+        if !auth::may_edit(principal, &company) {
+            return api_error(403, &format!("you may not edit couplings of company {:?}", company));
+        }
+    }
+
+    let coupling_id = match store_coupling(&db_txn, id, &vehicle_ids).await {
+        Ok(Some(ci)) => ci,
+        Ok(None) => return api_error(404, "coupling not found"),
+        Err(StoreCouplingError::DuplicateVehicle(vehicle_id)) => {
+            return api_error(400, &format!("vehicle {} is listed more than once", vehicle_id));
+        },
+        Err(StoreCouplingError::TooFewVehicles) => {
+            return api_error(400, "a coupling must contain at least two vehicles");
+        },
+        Err(StoreCouplingError::UnknownVehicle(vehicle_id)) => {
+            return api_error(400, &format!("unknown vehicle {}", vehicle_id));
+        },
+        Err(StoreCouplingError::MixedCompanies(first_company, other_company)) => {
+            return api_error(400, &format!(
+                "a coupling may only contain vehicles of one company, found {:?} and {:?}",
+                first_company, other_company,
+            ));
+        },
+        Err(StoreCouplingError::AlreadyCoupled { vehicle_id, company, veh_number, coupling_id }) => {
+            let mut details = serde_json::Map::new();
+            details.insert(
+                "conflicting_vehicle".to_owned(),
+                serde_json::json!({
+                    "id": vehicle_id,
+                    "company": company,
+                    "number": veh_number,
+                    "coupling_id": coupling_id,
+                }),
+            );
+            return api_error_with(
+                409,
+                &format!("vehicle {} is already part of coupling {}", veh_number, coupling_id),
+                details,
+            );
+        },
+        Err(StoreCouplingError::Database(e)) => {
+            if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                return api_error(409, "a vehicle is already part of another coupling");
+            }
+            error!("failed to store coupling (ID {:?}): {}", id, e);
+            return api_error(500, "internal server error");
+        },
+    };
+
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit coupling transaction: {}", e);
+        return api_error(500, "internal server error");
+    }
+
+    let coupling = match load_api_coupling(&db_conn, coupling_id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return api_error(404, "coupling not found"),
+        Err(r) => return r,
+    };
+    if id.is_none() {
+        let base_path = &config::current()
+            .http.base_path;
+        let mut response = api_json(201, &coupling);
+        if let Ok(location) = format!("{}/api/v1/couplings/{}", base_path, coupling_id).parse() {
+            response.headers_mut().insert("Location", location);
+        }
+        response
+    } else {
+        api_json(200, &coupling)
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle_coupling_delete(remote_addr: SocketAddr, principal: Option<&Principal>, id: i64) -> Response<Full<Bytes>> {
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return api_db_unavailable(e),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if let Err(e) = set_change_context(&db_txn, principal, Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return api_error(500, "internal server error");
+    }
+    let company_opt = match lock_coupling_company(&db_txn, id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return api_error(404, "coupling not found"),
+        Err(e) => {
+            error!("failed to obtain company of coupling {}: {}", id, e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if let Some(company) = company_opt {
+        if !auth::may_edit(principal, &company) {
+            return api_error(403, &format!("you may not delete couplings of company {:?}", company));
+        }
+    }
+    if let Err(e) = db_txn.execute("DELETE FROM bimdb.coupling_bims WHERE coupling_id = $1", &[&id]).await {
+        error!("failed to delete coupling {} vehicles: {}", id, e);
+        return api_error(500, "internal server error");
+    }
+    let affected_rows = match db_txn.execute("DELETE FROM bimdb.couplings WHERE id = $1", &[&id]).await {
+        Ok(ar) => ar,
+        Err(e) => {
+            error!("failed to delete coupling {}: {}", id, e);
+            return api_error(500, "internal server error");
+        },
+    };
+    if affected_rows == 0 {
+        return api_error(404, "coupling not found");
+    }
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit coupling deletion transaction: {}", e);
+        return api_error(500, "internal server error");
+    }
+    api_no_content()
+}
+
+async fn handle_couplings(remote_addr: SocketAddr, principal: Option<&Principal>, request: Request<Incoming>, id_opt: Option<i64>) -> Response<Full<Bytes>> {
+    let Some(id) = id_opt else {
+        return if request.method() == Method::GET {
+            handle_coupling_list(principal, request).await
+        } else if request.method() == Method::POST {
+            match read_json_body(request).await {
+                Ok(input) => store_api_coupling(remote_addr, principal, None, input).await,
+                Err(r) => r,
+            }
+        } else {
+            api_error_405(request.method(), &[Method::GET, Method::POST])
+        };
+    };
+
+    if request.method() == Method::GET {
+        let db_conn = match db_connect().await {
+            Ok(dbc) => dbc,
+            Err(e) => return api_db_unavailable(e),
+        };
+        match load_api_coupling(&db_conn, id).await {
+            Ok(Some(c)) if auth::view_scope(principal).includes(&c.company) => api_json(200, &c),
+            Ok(_) => api_error(404, "coupling not found"),
+            Err(r) => r,
+        }
+    } else if request.method() == Method::PUT {
+        match read_json_body(request).await {
+            Ok(input) => store_api_coupling(remote_addr, principal, Some(id), input).await,
+            Err(r) => r,
+        }
+    } else if request.method() == Method::DELETE {
+        handle_coupling_delete(remote_addr, principal, id).await
+    } else {
+        api_error_405(request.method(), &[Method::GET, Method::PUT, Method::DELETE])
+    }
+}
+
+
+/// Handles a request to the JSON API; `path` is the part of the path after `api/v1`.
+#[instrument(skip(request))]
+pub(crate) async fn handle_api(remote_addr: SocketAddr, request: Request<Incoming>, path: &[String]) -> Response<Full<Bytes>> {
+    let principal = auth::principal(&request);
+    let principal = principal.as_ref();
+    match path {
+        [resource] if resource == "vehicles" => handle_vehicles(remote_addr, principal, request, None).await,
+        [resource, id_str] if resource == "vehicles" => match id_str.parse() {
+            Ok(id) => handle_vehicles(remote_addr, principal, request, Some(id)).await,
+            Err(_) => api_error(404, "vehicle not found"),
+        },
+        [resource] if resource == "couplings" => handle_couplings(remote_addr, principal, request, None).await,
+        [resource, id_str] if resource == "couplings" => match id_str.parse() {
+            Ok(id) => handle_couplings(remote_addr, principal, request, Some(id)).await,
+            Err(_) => api_error(404, "coupling not found"),
+        },
+        _ => api_error(404, "not found"),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::merge_patch;
+    use serde_json::json;
+
+    fn patched(mut target: serde_json::Value, patch: serde_json::Value) -> serde_json::Value {
+        merge_patch(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn test_merge_patch_null_deletes() {
+        assert_eq!(
+            patched(json!({"a": "b", "c": "d"}), json!({"a": null})),
+            json!({"c": "d"}),
+        );
+        assert_eq!(
+            patched(json!({"c": "d"}), json!({"a": null})),
+            json!({"c": "d"}),
+        );
+    }
+
+    #[test]
+    fn test_merge_patch_adds_and_replaces_scalars() {
+        assert_eq!(
+            patched(json!({"a": "b"}), json!({"a": "c", "b": 2})),
+            json!({"a": "c", "b": 2}),
+        );
+        assert_eq!(
+            patched(json!({"a": "b"}), json!({"a": {"x": 1}})),
+            json!({"a": {"x": 1}}),
+        );
+        assert_eq!(
+            patched(json!({"a": {"x": 1}}), json!({"a": 5})),
+            json!({"a": 5}),
+        );
+    }
+
+    #[test]
+    fn test_merge_patch_nested_objects() {
+        assert_eq!(
+            patched(
+                json!({"a": {"b": "c", "d": "e"}, "f": "g"}),
+                json!({"a": {"b": "x", "d": null, "h": {"i": null}}}),
+            ),
+            json!({"a": {"b": "x", "h": {}}, "f": "g"}),
+        );
+    }
+
+    #[test]
+    fn test_merge_patch_replaces_arrays() {
+        assert_eq!(
+            patched(json!({"a": [1, 2, 3]}), json!({"a": [4]})),
+            json!({"a": [4]}),
+        );
+        assert_eq!(
+            patched(json!({"a": [{"b": "c"}]}), json!({"a": [1]})),
+            json!({"a": [1]}),
+        );
+        assert_eq!(
+            patched(json!(["a", "b"]), json!(["c", "d"])),
+            json!(["c", "d"]),
+        );
+    }
+
+    #[test]
+    fn test_merge_patch_non_object_patch() {
+        assert_eq!(patched(json!({"a": "b"}), json!(["c"])), json!(["c"]));
+        assert_eq!(patched(json!({"a": "foo"}), json!(null)), json!(null));
+        assert_eq!(patched(json!({"a": "foo"}), json!("bar")), json!("bar"));
+        assert_eq!(patched(json!(["a", "b"]), json!({"a": "c"})), json!({"a": "c"}));
+        assert_eq!(patched(json!("a"), json!({})), json!({}));
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use deadpool_postgres::Object;
+use tokio_postgres::error::SqlState;
+use tracing::info;
+
+use crate::{
+    begin_snapshot, DB_POOL, decode_vehicles, encode_export, Export, ExportedVehicle, ExportFormat, load_all_vehicles,
+    load_company_vehicles, set_change_context,
+};
+use crate::auth::{self, ALL_COMPANIES, MAX_PASSWORD_LENGTH, Role, ViewScope};
+use crate::import;
+use crate::migrations;
+use crate::value_sets;
+
+
+/// Local vehicle database manager for the rocketbot `bim` plugin.
+#[derive(Parser)]
+#[command(version)]
+pub(crate) struct Cli {
+    /// Path to the configuration file.
+    #[arg(short, long, global = true, default_value = "config.toml")]
+    pub config: PathBuf,
+
+    /// Path to the configuration file, as passed by earlier versions without a command.
+    #[arg(hide = true)]
+    pub legacy_config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Subcommand)]
+pub(crate) enum Command {
+    /// Serve the web interface and the API (the default).
+    Serve,
+
+    /// Set up the database schema or upgrade it to the version expected by this binary.
+    Migrate,
+
+    /// Export vehicles to standard output or a file.
+    Export {
+        /// Only export the vehicles of this company; required for CSV.
+        #[arg(long)]
+        company: Option<String>,
+
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+
+        /// Write the export to this file instead of standard output.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Import vehicles from a file, replacing the existing vehicles of the same numbers.
+    ///
+    /// Without --company, the file must be an export of all companies in JSON or CBOR format.
+    Import {
+        file: PathBuf,
+
+        /// Import the file as the vehicles of this company.
+        #[arg(long)]
+        company: Option<String>,
+
+        /// Format of the file; guessed from its extension if not given.
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+
+        /// Only output what the import would change.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Check whether the configuration file can be loaded.
+    CheckConfig {
+        /// Also connect to the database and check its schema version.
+        #[arg(long)]
+        connect: bool,
+    },
+
+    /// Create a user, reading the password from the first line of standard input.
+    AddUser {
+        username: String,
+
+        /// Assign this role (viewer, editor or admin) to the new user.
+        #[arg(long, value_parser = parse_role)]
+        role: Option<Role>,
+
+        /// The company for which to assign the role; "*" means all companies.
+        #[arg(long, default_value = ALL_COMPANIES, requires = "role")]
+        company: String,
+    },
+}
+
+
+fn parse_role(role: &str) -> Result<Role, String> {
+    Role::from_db(role)
+        .ok_or_else(|| format!("unknown role {:?}; expected viewer, editor or admin", role))
+}
+
+async fn connect() -> Result<Object, String> {
+    DB_POOL.get().expect("DB_POOL not set?!")
+        .get().await
+        .map_err(|e| format!("failed to connect to database: {}", e))
+}
+
+/// Borrows a connection from the pool after making sure the schema is up to date.
+async fn connect_checked() -> Result<Object, String> {
+    let db_conn = connect().await?;
+    migrations::check_schema_version(&db_conn).await?;
+    Ok(db_conn)
+}
+
+fn guess_format(path: &Path) -> Result<ExportFormat, String> {
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("json") => Ok(ExportFormat::Json),
+        Some("cbor") => Ok(ExportFormat::Cbor),
+        Some("csv") => Ok(ExportFormat::Csv),
+        _ => Err(format!("failed to guess the format of {}; pass --format", path.display())),
+    }
+}
+
+/// Parses an export of all companies, keyed by company.
+fn decode_all_vehicles(data: &[u8], format: ExportFormat) -> Result<BTreeMap<String, Vec<ExportedVehicle>>, String> {
+    match format {
+        ExportFormat::Json => serde_json::from_slice(data)
+            .map_err(|e| format!("failed to parse JSON: {}", e)),
+        ExportFormat::Cbor => ciborium::from_reader(data)
+            .map_err(|e| format!("failed to parse CBOR: {}", e)),
+        ExportFormat::Csv => Err("CSV files can only be imported with --company".to_owned()),
+    }
+}
+
+/// Reads a password from the terminal without echoing it or, if standard input is not a terminal,
+/// the first line of standard input.
+fn read_password(username: &str) -> Result<String, String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return rpassword::prompt_password(format!("Password for {:?}: ", username))
+            .map_err(|e| format!("failed to read password: {}", e));
+    }
+    let mut line = String::new();
+    stdin.lock().read_line(&mut line)
+        .map_err(|e| format!("failed to read password: {}", e))?;
+    let password = line.strip_suffix('\n').unwrap_or(&line);
+    let password = password.strip_suffix('\r').unwrap_or(password);
+    Ok(password.to_owned())
+}
+
+/// Sets up or upgrades the database schema.
+pub(crate) async fn migrate() -> Result<(), String> {
+    let mut db_conn = connect().await?;
+    migrations::migrate(&mut db_conn).await
+}
+
+/// Writes an export of one or all companies to the given file or standard output.
+pub(crate) async fn export(company: Option<String>, format: ExportFormat, output: Option<PathBuf>) -> Result<(), String> {
+    if company.is_none() && format == ExportFormat::Csv {
+        return Err("CSV can only export the vehicles of one company; pass --company".to_owned());
+    }
+
+    let mut db_conn = connect_checked().await?;
+    let db_txn = begin_snapshot(&mut db_conn).await
+        .map_err(|e| format!("failed to begin export transaction: {}", e))?;
+    let export = match company {
+        Some(c) => match load_company_vehicles(&db_txn, &c).await {
+            Some(v) => Export::Company(v),
+            None => return Err("failed to load vehicles".to_owned()),
+        },
+        None => match load_all_vehicles(&db_txn, &ViewScope::All).await {
+            Some(ctv) => Export::All(ctv),
+            None => return Err("failed to load vehicles".to_owned()),
+        },
+    };
+    let (data, _content_type) = encode_export(&export, format)?;
+
+    match output {
+        Some(path) => std::fs::write(&path, &data)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e)),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)
+                .and_then(|()| stdout.flush())
+                .map_err(|e| format!("failed to write export: {}", e))
+        },
+    }
+}
+
+/// Imports vehicles from a file in one transaction and outputs the summary (or, for a dry run, the
+/// plan) for each company as JSON.
+pub(crate) async fn import(file: PathBuf, company: Option<String>, format: Option<ExportFormat>, dry_run: bool) -> Result<(), String> {
+    let format = match format {
+        Some(f) => f,
+        None => guess_format(&file)?,
+    };
+    let data = std::fs::read(&file)
+        .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+    let mut company_to_vehicles = match company {
+        Some(c) => {
+            let c = c.trim();
+            if c.is_empty() {
+                return Err("company must not be empty".to_owned());
+            }
+            let mut ctv = BTreeMap::new();
+            ctv.insert(c.to_owned(), decode_vehicles(&data, format)?);
+            ctv
+        },
+        None => decode_all_vehicles(&data, format)?,
+    };
+
+    let mut db_conn = connect_checked().await?;
+    let db_txn = db_conn.transaction().await
+        .map_err(|e| format!("failed to begin database transaction: {}", e))?;
+    set_change_context(&db_txn, None, None).await
+        .map_err(|e| format!("failed to set change context: {}", e))?;
+    import::lock_for_import(&db_txn).await
+        .map_err(|e| format!("failed to lock tables for import: {}", e))?;
+
+    // validate against the locked state, as stored values are exempt from the value sets
+    let value_sets = value_sets::load_value_sets(&db_txn).await
+        .ok_or_else(|| "failed to load value sets".to_owned())?;
+    for (company, vehicles) in &mut company_to_vehicles {
+        let stored_vehicles = load_company_vehicles(&db_txn, company).await
+            .ok_or_else(|| format!("failed to load the vehicles of company {:?}", company))?;
+        import::validate_import(company, vehicles, &value_sets, &stored_vehicles)
+            .map_err(|e| format!("company {:?}: {}", company, e))?;
+    }
+
+    let mut results = BTreeMap::new();
+    for (company, vehicles) in &company_to_vehicles {
+        let plan = import::plan_import(&db_txn, company, vehicles).await
+            .ok_or_else(|| format!("failed to plan import for company {:?}", company))?;
+        let result = if dry_run {
+            serde_json::to_value(&plan)
+        } else {
+            let summary = import::apply_import(&db_txn, vehicles, &plan).await
+                .map_err(|e| format!("failed to import vehicles for company {:?}: {}", company, e))?;
+            serde_json::to_value(summary)
+        };
+        let result_value = result
+            .map_err(|e| format!("failed to serialize import result: {}", e))?;
+        results.insert(company.clone(), result_value);
+    }
+
+    // a dry run rolls back when the transaction is dropped
+    if !dry_run {
+        db_txn.commit().await
+            .map_err(|e| format!("failed to commit import transaction: {}", e))?;
+        info!("imported {} from the command line", file.display());
+    }
+
+    let results_json = serde_json::to_string_pretty(&results)
+        .map_err(|e| format!("failed to serialize import results: {}", e))?;
+    println!("{}", results_json);
+    Ok(())
+}
+
+/// Reports whether the configuration (which has already been loaded at this point) is usable.
+pub(crate) async fn check_config(connect_db: bool) -> Result<(), String> {
+    if connect_db {
+        let db_conn = connect().await?;
+        migrations::check_schema_version(&db_conn).await?;
+        println!("configuration is valid; database schema is at version {}", migrations::SCHEMA_VERSION);
+    } else {
+        println!("configuration is valid");
+    }
+    Ok(())
+}
+
+/// Creates a user with the password read from standard input, optionally assigning a role.
+pub(crate) async fn add_user(username: String, role: Option<Role>, company: String) -> Result<(), String> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err("username must not be empty".to_owned());
+    }
+    if username.len() > 256 {
+        return Err("username must be at most 256 bytes long".to_owned());
+    }
+    let company = company.trim();
+    if company.is_empty() {
+        return Err("company must not be empty".to_owned());
+    }
+    if company.chars().count() > 256 {
+        return Err("company must not be longer than 256 characters".to_owned());
+    }
+
+    let password = read_password(username)?;
+    if password.is_empty() {
+        return Err("password must not be empty".to_owned());
+    }
+    if password.len() > MAX_PASSWORD_LENGTH {
+        return Err(format!("password must be at most {} bytes long", MAX_PASSWORD_LENGTH));
+    }
+    let password_hash = auth::hash_password(&password).await
+        .map_err(|e| format!("failed to hash password: {}", e))?;
+
+    let mut db_conn = connect_checked().await?;
+    let db_txn = db_conn.transaction().await
+        .map_err(|e| format!("failed to begin database transaction: {}", e))?;
+    let insert_res = db_txn.query_one(
+        "INSERT INTO bimdb.users (id, username, password_hash) VALUES (DEFAULT, $1, $2) RETURNING id",
+        &[&username, &password_hash],
+    ).await;
+    let user_id: i64 = match insert_res {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                return Err("a user with this name already exists".to_owned());
+            }
+            return Err(format!("failed to create user {:?}: {}", username, e));
+        },
+    };
+    if let Some(role) = role {
+        db_txn.execute(
+            "INSERT INTO bimdb.user_roles (user_id, company, role) VALUES ($1, $2, $3)",
+            &[&user_id, &company, &role.as_db()],
+        ).await
+            .map_err(|e| format!("failed to assign role to user {:?}: {}", username, e))?;
+    }
+    db_txn.commit().await
+        .map_err(|e| format!("failed to commit user creation: {}", e))?;
+
+    match role {
+        Some(r) => info!("created user {:?} with role {:?} for company {:?}", username, r, company),
+        None => info!("created user {:?}", username),
+    }
+    Ok(())
+}
@@ -1,11 +1,47 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
 use std::net::SocketAddr;
-use std::sync::OnceLock;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, OnceLock};
 
+use arc_swap::ArcSwap;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
 
-pub(crate) static CONFIG: OnceLock<Config> = OnceLock::new();
+static CONFIG: OnceLock<ArcSwap<Config>> = OnceLock::new();
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Prefix of the environment variables which override configuration values.
+const ENV_PREFIX: &str = "BIMDB_";
+
+/// Separates the section from the key in the names of overriding environment variables.
+const ENV_PATH_SEPARATOR: &str = "__";
+
+/// Matches values quoted in backticks or double quotes in problem messages.
+static QUOTED_VALUE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(
+    r#"`[^`]*`|"(?:[^"\\]|\\.)*""#,
+).expect("failed to compile quoted value regex"));
+
+/// The paths of the values which are strings. Overrides for them are taken verbatim instead of being
+/// parsed as TOML, so that e.g. a numeric password remains a string.
+const STRING_VALUE_PATHS: [&str; 13] = [
+    "http.listen_socket_addr",
+    "http.base_path",
+    "http.static_path",
+    "db.username",
+    "db.password",
+    "db.password_file",
+    "db.hostname",
+    "db.db_name",
+    "db.socket_path",
+    "db.ssl_mode",
+    "db.ssl_root_cert",
+    "db.ssl_client_cert",
+    "db.ssl_client_key",
+];
 
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -14,9 +50,401 @@ pub struct Config {
     pub db: DbConfig,
     #[serde(default = "Config::default_vehicles_per_page")] pub vehicles_per_page: i64,
     #[serde(default)] pub value_sets: ValueSetConfig,
+    #[serde(default)] pub auth: AuthConfig,
 }
 impl Config {
     fn default_vehicles_per_page() -> i64 { 20 }
+
+    /// Reads and parses the configuration file at the given path, applies the overrides from
+    /// `BIMDB_*` environment variables and reads the database password from `db.password_file`.
+    ///
+    /// Logs where each value was taken from, without the values themselves.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let config_bytes = std::fs::read(path)
+            .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+        let config_string = String::from_utf8(config_bytes)
+            .map_err(|e| format!("failed to decode config file {} as UTF-8: {}", path.display(), e))?;
+        let mut env_vars: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+        env_vars.sort();
+        Self::load_str(path, &config_string, env_vars)
+    }
+
+    /// Parses the text of the configuration file at the given path, applying the overrides from the
+    /// given environment variables.
+    fn load_str(path: &Path, config_string: &str, env_vars: Vec<(OsString, OsString)>) -> Result<Self, String> {
+        let mut table: toml::Table = toml::from_str(config_string)
+            .map_err(|e| format!("failed to parse config file {}: {}", path.display(), e))?;
+
+        let mut sources = BTreeMap::new();
+        collect_value_paths(&table, "", &mut |value_path| {
+            sources.insert(value_path, ValueSource::File);
+        });
+        apply_env_overrides(&mut table, env_vars.into_iter(), &mut sources)?;
+        resolve_password_file(&mut table, &mut sources)?;
+
+        let mut by_source: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (value_path, source) in &sources {
+            let source_desc = match source {
+                ValueSource::File => format!("file {}", path.display()),
+                ValueSource::Environment(var_name) => format!("environment variable {}", var_name),
+                ValueSource::PasswordFile(password_path) => format!("password file {}", password_path),
+            };
+            by_source.entry(source_desc).or_default().push(value_path);
+        }
+        let source_descs: Vec<String> = by_source.iter()
+            .map(|(source_desc, value_paths)| format!("{} from {}", value_paths.join(", "), source_desc))
+            .collect();
+        info!("configuration: {}; defaults for everything else", source_descs.join("; "));
+
+        // collect all problems instead of stopping at the first one
+        let mut problems = Vec::new();
+        check_value_sets(&table, &mut problems);
+        let mut unknown_keys = Vec::new();
+        let mut track = serde_path_to_error::Track::new();
+        let deserializer = serde_path_to_error::Deserializer::new(toml::Value::Table(table), &mut track);
+        let config_res: Result<Config, toml::de::Error> = serde_ignored::deserialize(
+            deserializer,
+            |unknown_path| unknown_keys.push(unknown_path.to_string()),
+        );
+        for unknown_key in unknown_keys {
+            problems.push((unknown_key, "unknown key".to_owned()));
+        }
+        let config_opt = match config_res {
+            Ok(c) => {
+                c.validate(&mut problems);
+                Some(c)
+            },
+            Err(e) => {
+                problems.push((track.path().to_string(), e.message().to_owned()));
+                None
+            },
+        };
+        if let Some(config) = config_opt {
+            if problems.is_empty() {
+                return Ok(config);
+            }
+        }
+
+        let mut file_spans = BTreeMap::new();
+        if let Ok(de_table) = toml::de::DeTable::parse(config_string) {
+            collect_value_spans(de_table.get_ref(), "", &mut file_spans);
+        }
+        let locator = ValueLocator {
+            path,
+            text: config_string,
+            spans: &file_spans,
+            sources: &sources,
+        };
+        let problem_lines: Vec<String> = problems.iter()
+            .map(|(value_path, message)| {
+                // values from outside the file may be secrets
+                let message = if locator.is_overridden(value_path) {
+                    hide_values(message)
+                } else {
+                    message.clone()
+                };
+                format!("  {}: {}: {}", locator.locate(value_path), value_path, message)
+            })
+            .collect();
+        Err(format!("invalid configuration:\n{}", problem_lines.join("\n")))
+    }
+
+    /// Checks the values which deserialize fine but make no sense, adding a `(path, message)` pair to
+    /// `problems` for each.
+    fn validate(&self, problems: &mut Vec<(String, String)>) {
+        let mut problem = |value_path: &str, message: &str| {
+            problems.push((value_path.to_owned(), message.to_owned()));
+        };
+
+        let base_path = &self.http.base_path;
+        if base_path == "/" {
+            problem("http.base_path", "must be empty (not \"/\") to serve from the root");
+        } else if !base_path.is_empty() && !base_path.starts_with('/') {
+            problem("http.base_path", "must start with a slash");
+        } else if base_path.ends_with('/') {
+            problem("http.base_path", "must not end with a slash");
+        }
+        if let Some(static_path) = &self.http.static_path {
+            if !Path::new(static_path).is_dir() {
+                problem("http.static_path", &format!("directory {:?} does not exist", static_path));
+            }
+        }
+
+        if self.db.pool_max_size == 0 {
+            problem("db.pool_max_size", "must be greater than zero");
+        }
+        if self.vehicles_per_page <= 0 {
+            problem("vehicles_per_page", "must be greater than zero");
+        }
+        if self.auth.session_lifetime_s <= 0 {
+            problem("auth.session_lifetime_s", "must be greater than zero");
+        }
+    }
+}
+
+/// Checks the value sets for empty and duplicate entries, which are lost once they are collected
+/// into sets.
+fn check_value_sets(table: &toml::Table, problems: &mut Vec<(String, String)>) {
+    let Some(toml::Value::Table(value_sets)) = table.get("value_sets") else {
+        return;
+    };
+    for (set_name, set_value) in value_sets {
+        let toml::Value::Array(entries) = set_value else { continue };
+        let mut first_indexes: BTreeMap<&str, usize> = BTreeMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            let toml::Value::String(entry_str) = entry else { continue };
+            let entry_path = format!("value_sets.{}[{}]", set_name, index);
+            if entry_str.trim().is_empty() {
+                problems.push((entry_path, "entry must not be empty".to_owned()));
+            } else if let Some(first_index) = first_indexes.get(entry_str.as_str()) {
+                problems.push((entry_path, format!("duplicate of entry {} ({:?})", first_index, entry_str)));
+            } else {
+                first_indexes.insert(entry_str, index);
+            }
+        }
+    }
+}
+
+/// Finds out where the value at a given path was taken from for error messages.
+struct ValueLocator<'a> {
+    path: &'a Path,
+    text: &'a str,
+    spans: &'a BTreeMap<String, Range<usize>>,
+    sources: &'a BTreeMap<String, ValueSource>,
+}
+impl<'a> ValueLocator<'a> {
+    /// Whether the value at the given path, or a value or section enclosing it, was taken from an
+    /// environment variable or password file.
+    fn is_overridden(&self, value_path: &str) -> bool {
+        Self::enclosing_paths(value_path)
+            .any(|candidate| matches!(
+                self.sources.get(candidate),
+                Some(ValueSource::Environment(_))|Some(ValueSource::PasswordFile(_)),
+            ))
+    }
+
+    /// Describes the location of the value at the given path, or of the closest enclosing value or
+    /// section if it has no location of its own.
+    fn locate(&self, value_path: &str) -> String {
+        // an override replaces everything below it, including any values from the file
+        for candidate in Self::enclosing_paths(value_path) {
+            match self.sources.get(candidate) {
+                Some(ValueSource::Environment(var_name)) => return format!("environment variable {}", var_name),
+                Some(ValueSource::PasswordFile(password_path)) => return format!("password file {}", password_path),
+                Some(ValueSource::File)|None => {},
+            }
+        }
+        for candidate in Self::enclosing_paths(value_path) {
+            if let Some(span) = self.spans.get(candidate) {
+                let before = &self.text[..span.start];
+                let line = before.matches('\n').count() + 1;
+                let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+                return format!("{}:{}:{}", self.path.display(), line, column);
+            }
+        }
+        self.path.display().to_string()
+    }
+
+    /// Returns the given path followed by the paths of the values and sections enclosing it.
+    fn enclosing_paths(value_path: &str) -> impl Iterator<Item = &str> {
+        std::iter::successors(Some(value_path), |candidate| {
+            candidate.rfind(['.', '[']).map(|index| &candidate[..index])
+        })
+    }
+}
+
+/// Replaces the values quoted in a problem message, which serde encloses in backticks and
+/// [`Config::validate`] in double quotes.
+fn hide_values(message: &str) -> String {
+    QUOTED_VALUE_REGEX.replace_all(message, "(value hidden)")
+        .into_owned()
+}
+
+/// Collects the span of each key (for tables) or value (otherwise) in the parsed file, keyed by
+/// its dotted path with array indexes in brackets.
+fn collect_value_spans(table: &toml::de::DeTable<'_>, prefix: &str, spans: &mut BTreeMap<String, Range<usize>>) {
+    for (key, value) in table {
+        let value_path = format!("{}{}", prefix, key.get_ref());
+        collect_value_span(value, value_path, key.span(), spans);
+    }
+}
+
+fn collect_value_span(value: &toml::Spanned<toml::de::DeValue<'_>>, value_path: String, key_span: Range<usize>, spans: &mut BTreeMap<String, Range<usize>>) {
+    match value.get_ref() {
+        toml::de::DeValue::Table(subtable) => {
+            collect_value_spans(subtable, &format!("{}.", value_path), spans);
+            spans.insert(value_path, key_span);
+        },
+        toml::de::DeValue::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                collect_value_span(item, format!("{}[{}]", value_path, index), item.span(), spans);
+            }
+            spans.insert(value_path, value.span());
+        },
+        _ => {
+            spans.insert(value_path, value.span());
+        },
+    }
+}
+
+/// Loads the configuration from the given path and makes it the live configuration.
+pub(crate) fn init(path: &Path) -> Result<(), String> {
+    let config = Config::load(path)?;
+    CONFIG_PATH.set(path.to_owned())
+        .expect("CONFIG_PATH already set?!");
+    if CONFIG.set(ArcSwap::from_pointee(config)).is_err() {
+        panic!("CONFIG already set?!");
+    }
+    Ok(())
+}
+
+/// Returns the live configuration.
+///
+/// Handlers should obtain it once and use it for the whole request so that a concurrent reload
+/// does not mix values from two configurations.
+pub(crate) fn current() -> Arc<Config> {
+    CONFIG.get().expect("CONFIG not set?!")
+        .load_full()
+}
+
+/// Re-reads the configuration file and swaps it in as the live configuration. If the new
+/// configuration is invalid, the previous one remains in effect.
+///
+/// The listening address and the database settings are only applied on restart; changes to them are
+/// reported and otherwise ignored.
+pub(crate) fn reload() -> Result<(), String> {
+    let path = CONFIG_PATH.get().expect("CONFIG_PATH not set?!");
+    let mut new_config = Config::load(path)?;
+
+    let live_config = CONFIG.get().expect("CONFIG not set?!");
+    let old_config = live_config.load();
+    if new_config.http.listen_socket_addr != old_config.http.listen_socket_addr {
+        warn!("changes to http.listen_socket_addr require a restart");
+        new_config.http.listen_socket_addr = old_config.http.listen_socket_addr;
+    }
+    if new_config.db != old_config.db {
+        warn!("changes to the db section require a restart");
+        new_config.db = old_config.db.clone();
+    }
+    live_config.store(Arc::new(new_config));
+    info!("configuration reloaded from {}", path.display());
+    Ok(())
+}
+
+/// Where a configuration value was taken from.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum ValueSource {
+    File,
+    Environment(String),
+    PasswordFile(String),
+}
+
+/// Calls `found` with the dotted path of each value in the table, descending into subtables.
+fn collect_value_paths<F: FnMut(String)>(table: &toml::Table, prefix: &str, found: &mut F) {
+    for (key, value) in table {
+        let value_path = format!("{}{}", prefix, key);
+        if let toml::Value::Table(subtable) = value {
+            collect_value_paths(subtable, &format!("{}.", value_path), found);
+        } else {
+            found(value_path);
+        }
+    }
+}
+
+/// Parses the value of an environment variable overriding the value at the given path.
+///
+/// Values of [`STRING_VALUE_PATHS`] are always strings. Otherwise, values which are valid TOML (such as
+/// `5432`, `true` or `["tram", "bus"]`) are taken as such and anything else as a string; a value can
+/// be forced to be a string by quoting it TOML-style.
+fn parse_env_value(value_path: &str, value: &str) -> toml::Value {
+    if STRING_VALUE_PATHS.contains(&value_path) {
+        return toml::Value::String(value.to_owned());
+    }
+    let parsed: Result<toml::Table, _> = toml::from_str(&format!("value = {}", value));
+    match parsed {
+        Ok(mut table) if table.len() == 1 => table.remove("value")
+            .expect("parsed table has no value"),
+        _ => toml::Value::String(value.to_owned()),
+    }
+}
+
+/// Applies the `BIMDB_*` environment variables to the table.
+///
+/// `BIMDB_VEHICLES_PER_PAGE` sets `vehicles_per_page` and `BIMDB_DB__PASSWORD` sets `password` in
+/// section `db`; the names are lowercased and double underscores separate the section from the key.
+fn apply_env_overrides<I: Iterator<Item = (OsString, OsString)>>(table: &mut toml::Table, vars: I, sources: &mut BTreeMap<String, ValueSource>) -> Result<(), String> {
+    for (var_name_os, var_value_os) in vars {
+        let Some(var_name) = var_name_os.to_str() else { continue };
+        let Some(path_str) = var_name.strip_prefix(ENV_PREFIX) else { continue };
+        let Some(var_value) = var_value_os.to_str() else {
+            return Err(format!("environment variable {} is not valid UTF-8", var_name));
+        };
+
+        let path_parts: Vec<String> = path_str.split(ENV_PATH_SEPARATOR)
+            .map(|part| part.to_lowercase())
+            .collect();
+        if path_parts.iter().any(|part| part.is_empty()) {
+            return Err(format!("environment variable {} does not name a configuration value", var_name));
+        }
+        let (key, sections) = path_parts.split_last()
+            .expect("split always returns at least one part");
+
+        let mut current_table = &mut *table;
+        for section in sections {
+            let entry = current_table.entry(section.clone())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            current_table = match entry {
+                toml::Value::Table(t) => t,
+                _ => return Err(format!("environment variable {}: {:?} is not a section", var_name, section)),
+            };
+        }
+        let value_path = path_parts.join(".");
+        current_table.insert(key.clone(), parse_env_value(&value_path, var_value));
+
+        // the value may replace a whole section
+        let section_prefix = format!("{}.", value_path);
+        sources.retain(|p, _source| !p.starts_with(&section_prefix));
+        sources.insert(value_path, ValueSource::Environment(var_name.to_owned()));
+    }
+    Ok(())
+}
+
+/// Replaces `db.password_file` with `db.password` read from that file.
+///
+/// If both are given, the one given by an environment variable wins; giving both in the same place
+/// is an error.
+fn resolve_password_file(table: &mut toml::Table, sources: &mut BTreeMap<String, ValueSource>) -> Result<(), String> {
+    let Some(toml::Value::Table(db_table)) = table.get_mut("db") else {
+        return Ok(());
+    };
+    let Some(password_file_value) = db_table.remove("password_file") else {
+        return Ok(());
+    };
+    let password_file_source = sources.remove("db.password_file")
+        .expect("db.password_file has no source");
+    let toml::Value::String(password_path) = password_file_value else {
+        return Err("db.password_file must be a string".to_owned());
+    };
+
+    match sources.get("db.password") {
+        None => {},
+        Some(ValueSource::Environment(_)) if password_file_source == ValueSource::File => {
+            // the password in the environment wins over the password file in the config file
+            return Ok(());
+        },
+        Some(ValueSource::File) if password_file_source != ValueSource::File => {
+            // the password file in the environment wins over the password in the config file
+        },
+        Some(_) => return Err("only one of db.password and db.password_file may be set".to_owned()),
+    }
+
+    let password_string = std::fs::read_to_string(&password_path)
+        .map_err(|e| format!("failed to read password file {}: {}", password_path, e))?;
+    let password = password_string.strip_suffix('\n').unwrap_or(&password_string);
+    let password = password.strip_suffix('\r').unwrap_or(password);
+    db_table.insert("password".to_owned(), toml::Value::String(password.to_owned()));
+    sources.insert("db.password".to_owned(), ValueSource::PasswordFile(password_path));
+    Ok(())
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -29,17 +457,346 @@ pub struct HttpConfig {
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct DbConfig {
     pub username: String,
+    /// The password; can also be read from the file named by `password_file` when loading.
     pub password: String,
-    pub hostname: String,
+    #[serde(default = "DbConfig::default_hostname")] pub hostname: String,
     pub db_name: String,
     #[serde(default = "DbConfig::default_port")] pub port: u16,
+    #[serde(default)] pub socket_path: Option<String>,
+    #[serde(default)] pub ssl_mode: DbSslMode,
+    #[serde(default)] pub ssl_root_cert: Option<String>,
+    #[serde(default)] pub ssl_client_cert: Option<String>,
+    #[serde(default)] pub ssl_client_key: Option<String>,
+    #[serde(default = "DbConfig::default_pool_max_size")] pub pool_max_size: usize,
+    #[serde(default = "DbConfig::default_pool_wait_timeout_ms")] pub pool_wait_timeout_ms: u64,
+    #[serde(default = "DbConfig::default_pool_idle_timeout_s")] pub pool_idle_timeout_s: u64,
+    #[serde(default = "DbConfig::default_pool_health_check")] pub pool_health_check: bool,
 }
 impl DbConfig {
+    fn default_hostname() -> String { "localhost".to_owned() }
     fn default_port() -> u16 { 5432 }
+    fn default_pool_max_size() -> usize { 16 }
+    fn default_pool_wait_timeout_ms() -> u64 { 2000 }
+    fn default_pool_idle_timeout_s() -> u64 { 300 }
+    fn default_pool_health_check() -> bool { true }
+}
+
+/// Whether and how the database connection is encrypted.
+///
+/// `prefer` and `require` only verify the server's certificate against `ssl_root_cert` if it is set,
+/// without checking the hostname; `verify-full` verifies the certificate against the system's trusted
+/// certificates (or `ssl_root_cert`, if set) as well as the hostname.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DbSslMode {
+    #[default] Disable,
+    Prefer,
+    Require,
+    VerifyFull,
 }
 
+/// Allowed vehicle classes and power sources from earlier versions, which are now managed in the
+/// database.
+///
+/// `bimdatabase migrate` adds these values to the database once when it creates the value set
+/// tables; the section is ignored otherwise.
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct ValueSetConfig {
     #[serde(default)] pub vehicle_classes: BTreeSet<String>,
     #[serde(default)] pub power_sources: BTreeSet<String>,
 }
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct AuthConfig {
+    #[serde(default = "AuthConfig::default_public_read")] pub public_read: bool,
+    #[serde(default = "AuthConfig::default_session_lifetime_s")] pub session_lifetime_s: i64,
+    #[serde(default)] pub secure_cookie: bool,
+}
+impl AuthConfig {
+    fn default_public_read() -> bool { true }
+    fn default_session_lifetime_s() -> i64 { 30 * 24 * 60 * 60 }
+}
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            public_read: Self::default_public_read(),
+            session_lifetime_s: Self::default_session_lifetime_s(),
+            secure_cookie: false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A configuration setting every value, each of which is valid.
+    const FULL_CONFIG: &str = r#"
+vehicles_per_page = 20
+
+[http]
+listen_socket_addr = "127.0.0.1:8080"
+base_path = "/bimdb"
+static_path = "/"
+
+[db]
+username = "bimdb"
+password = "secret"
+hostname = "localhost"
+db_name = "bimdb"
+port = 5432
+socket_path = "/run/postgresql"
+ssl_mode = "require"
+ssl_root_cert = "root.crt"
+ssl_client_cert = "client.crt"
+ssl_client_key = "client.key"
+pool_max_size = 16
+pool_wait_timeout_ms = 2000
+pool_idle_timeout_s = 300
+pool_health_check = true
+
+[value_sets]
+vehicle_classes = ["tram"]
+power_sources = ["overhead-line"]
+
+[auth]
+public_read = true
+session_lifetime_s = 3600
+secure_cookie = true
+"#;
+
+    /// A minimal valid configuration.
+    const MINIMAL_CONFIG: &str = r#"[http]
+listen_socket_addr = "127.0.0.1:8080"
+base_path = ""
+
+[db]
+username = "bimdb"
+password = "secret"
+db_name = "bimdb"
+"#;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)))
+            .collect()
+    }
+
+    fn load(config_string: &str, vars: &[(&str, &str)]) -> Result<Config, String> {
+        Config::load_str(Path::new("test.toml"), config_string, env(vars))
+    }
+
+    /// Writes a password file which is deleted when the returned guard is dropped.
+    struct PasswordFile(PathBuf);
+    impl PasswordFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("bimdb-test-{}-{}", std::process::id(), name));
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+    impl Drop for PasswordFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_minimal_config() {
+        let config = load(MINIMAL_CONFIG, &[]).unwrap();
+        assert_eq!(config.db.hostname, "localhost");
+        assert_eq!(config.db.port, 5432);
+        assert_eq!(config.vehicles_per_page, 20);
+        assert!(config.auth.public_read);
+    }
+
+    #[test]
+    fn test_validation_problems() {
+        let config_string = r#"vehicles_per_page = 0
+
+[http]
+listen_socket_addr = "127.0.0.1:8080"
+base_path = "/"
+colour = "blue"
+
+[db]
+username = "bimdb"
+password = "secret"
+db_name = "bimdb"
+
+[value_sets]
+vehicle_classes = ["tram", " ", "tram"]
+"#;
+        let error = load(config_string, &[]).unwrap_err();
+        let mut problems: Vec<&str> = error.lines().collect();
+        assert_eq!(problems.remove(0), "invalid configuration:");
+        problems.sort_unstable();
+        assert_eq!(problems, [
+            "  test.toml:14:28: value_sets.vehicle_classes[1]: entry must not be empty",
+            "  test.toml:14:33: value_sets.vehicle_classes[2]: duplicate of entry 0 (\"tram\")",
+            "  test.toml:1:21: vehicles_per_page: must be greater than zero",
+            "  test.toml:5:13: http.base_path: must be empty (not \"/\") to serve from the root",
+            "  test.toml:6:10: http.colour: unknown key",
+        ]);
+    }
+
+    #[test]
+    fn test_type_problem() {
+        let config_string = MINIMAL_CONFIG.replace("db_name = \"bimdb\"", "db_name = \"bimdb\"\nport = \"five\"");
+        let error = load(&config_string, &[]).unwrap_err();
+        assert_eq!(error, "invalid configuration:\n  test.toml:9:8: db.port: invalid type: string \"five\", expected u16");
+    }
+
+    #[test]
+    fn test_missing_value_problem() {
+        let config_string = MINIMAL_CONFIG.replace("db_name = \"bimdb\"\n", "");
+        let error = load(&config_string, &[]).unwrap_err();
+        assert_eq!(error, "invalid configuration:\n  test.toml:5:2: db: missing field `db_name`");
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let config = load(MINIMAL_CONFIG, &[
+            ("BIMDB_DB__PORT", "5433"),
+            ("BIMDB_DB__PASSWORD", "123456"),
+            ("BIMDB_AUTH__PUBLIC_READ", "false"),
+            ("BIMDB_VEHICLES_PER_PAGE", "50"),
+            ("BIMDB_VALUE_SETS__POWER_SOURCES", r#"["diesel", "battery"]"#),
+            ("OTHER_DB__PORT", "1"),
+        ]).unwrap();
+        assert_eq!(config.db.port, 5433);
+        assert_eq!(config.db.password, "123456");
+        assert!(!config.auth.public_read);
+        assert_eq!(config.vehicles_per_page, 50);
+        assert_eq!(config.value_sets.power_sources, BTreeSet::from(["battery".to_owned(), "diesel".to_owned()]));
+    }
+
+    #[test]
+    fn test_env_override_sources() {
+        let mut table: toml::Table = toml::from_str(MINIMAL_CONFIG).unwrap();
+        let mut sources = BTreeMap::new();
+        collect_value_paths(&table, "", &mut |value_path| {
+            sources.insert(value_path, ValueSource::File);
+        });
+        let vars = env(&[("BIMDB_HTTP", "{ listen_socket_addr = \"[::1]:80\", base_path = \"/b\" }"), ("BIMDB_DB__PORT", "5433")]);
+        apply_env_overrides(&mut table, vars.into_iter(), &mut sources).unwrap();
+
+        assert_eq!(table["db"]["port"].as_integer(), Some(5433));
+        assert_eq!(table["http"]["base_path"].as_str(), Some("/b"));
+        // the override of a whole section replaces the sources of its values
+        assert_eq!(sources.get("http"), Some(&ValueSource::Environment("BIMDB_HTTP".to_owned())));
+        assert_eq!(sources.get("http.base_path"), None);
+        assert_eq!(sources.get("db.port"), Some(&ValueSource::Environment("BIMDB_DB__PORT".to_owned())));
+        assert_eq!(sources.get("db.username"), Some(&ValueSource::File));
+    }
+
+    #[test]
+    fn test_env_override_errors() {
+        let error = load(MINIMAL_CONFIG, &[("BIMDB_DB____PORT", "5433")]).unwrap_err();
+        assert_eq!(error, "environment variable BIMDB_DB____PORT does not name a configuration value");
+
+        let error = load(MINIMAL_CONFIG, &[("BIMDB_DB__USERNAME__FIRST", "bim")]).unwrap_err();
+        assert_eq!(error, "environment variable BIMDB_DB__USERNAME__FIRST: \"username\" is not a section");
+
+        let error = load(MINIMAL_CONFIG, &[("BIMDB_DB__PORT", "ninety")]).unwrap_err();
+        assert_eq!(error, "invalid configuration:\n  environment variable BIMDB_DB__PORT: db.port: invalid type: string (value hidden), expected u16");
+
+        let error = load(MINIMAL_CONFIG, &[("BIMDB_DB__COLOUR", "blue")]).unwrap_err();
+        assert_eq!(error, "invalid configuration:\n  environment variable BIMDB_DB__COLOUR: db.colour: unknown key");
+    }
+
+    #[test]
+    fn test_password_file() {
+        let password_file = PasswordFile::new("only", "from file\n");
+        let config_string = MINIMAL_CONFIG.replace("password = \"secret\"", &format!("password_file = {:?}", password_file.path()));
+        let config = load(&config_string, &[]).unwrap();
+        assert_eq!(config.db.password, "from file");
+    }
+
+    #[test]
+    fn test_password_file_precedence() {
+        let password_file = PasswordFile::new("precedence", "from file\r\n");
+        let file_with_both = MINIMAL_CONFIG.replace(
+            "password = \"secret\"",
+            &format!("password = \"secret\"\npassword_file = {:?}", password_file.path()),
+        );
+        let file_with_password_file = MINIMAL_CONFIG.replace("password = \"secret\"", &format!("password_file = {:?}", password_file.path()));
+
+        // both in the file
+        let error = load(&file_with_both, &[]).unwrap_err();
+        assert_eq!(error, "only one of db.password and db.password_file may be set");
+
+        // the password file in the environment wins over the password in the file
+        let config = load(MINIMAL_CONFIG, &[("BIMDB_DB__PASSWORD_FILE", password_file.path())]).unwrap();
+        assert_eq!(config.db.password, "from file");
+
+        // the password in the environment wins over the password file in the file
+        let config = load(&file_with_password_file, &[("BIMDB_DB__PASSWORD", "from env")]).unwrap();
+        assert_eq!(config.db.password, "from env");
+
+        // both in the environment
+        let error = load(MINIMAL_CONFIG, &[
+            ("BIMDB_DB__PASSWORD", "from env"),
+            ("BIMDB_DB__PASSWORD_FILE", password_file.path()),
+        ]).unwrap_err();
+        assert_eq!(error, "only one of db.password and db.password_file may be set");
+    }
+
+    #[test]
+    fn test_missing_password_file() {
+        let config_string = MINIMAL_CONFIG.replace("password = \"secret\"", "password_file = \"/nonexistent/bimdb-password\"");
+        let error = load(&config_string, &[]).unwrap_err();
+        assert!(error.starts_with("failed to read password file /nonexistent/bimdb-password: "), "{}", error);
+    }
+
+    #[test]
+    fn test_string_value_paths_complete() {
+        let config: Config = toml::from_str(FULL_CONFIG).unwrap();
+        let table = toml::Table::try_from(&config).unwrap();
+        let mut value_paths = Vec::new();
+        collect_value_paths(&table, "", &mut |value_path| value_paths.push(value_path));
+        for value_path in value_paths {
+            let (section, key) = value_path.split_once('.').unwrap_or(("", &value_path));
+            let value = if section.is_empty() { &table[key] } else { &table[section][key] };
+            assert_eq!(
+                value.is_str(),
+                STRING_VALUE_PATHS.contains(&value_path.as_str()),
+                "{} is missing from or wrongly in STRING_VALUE_PATHS",
+                value_path,
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_env_value() {
+        assert_eq!(parse_env_value("db.password", "123456"), toml::Value::String("123456".to_owned()));
+        assert_eq!(parse_env_value("db.password", "\"quoted\""), toml::Value::String("\"quoted\"".to_owned()));
+        assert_eq!(parse_env_value("db.hostname", "true"), toml::Value::String("true".to_owned()));
+        assert_eq!(parse_env_value("db.port", "5433"), toml::Value::Integer(5433));
+        assert_eq!(parse_env_value("auth.public_read", "false"), toml::Value::Boolean(false));
+        assert_eq!(parse_env_value("vehicles_per_page", "many"), toml::Value::String("many".to_owned()));
+        assert_eq!(
+            parse_env_value("value_sets.power_sources", r#"["diesel", "battery"]"#),
+            toml::Value::Array(vec![toml::Value::String("diesel".to_owned()), toml::Value::String("battery".to_owned())]),
+        );
+    }
+
+    #[test]
+    fn test_hide_values() {
+        assert_eq!(
+            hide_values("invalid type: integer `123456`, expected a string"),
+            "invalid type: integer (value hidden), expected a string",
+        );
+        assert_eq!(
+            hide_values(r#"directory "/secret \"path\"" does not exist"#),
+            "directory (value hidden) does not exist",
+        );
+        assert_eq!(hide_values("must be greater than zero"), "must be greater than zero");
+    }
+}
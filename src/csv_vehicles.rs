@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ExportedVehicle;
+
+
+/// Separates the entries of list-valued columns (power sources, fixed coupling).
+const LIST_SEPARATOR: char = '|';
+
+/// Prefix of the columns containing the entries of `other_data`.
+const OTHER_DATA_PREFIX: &str = "other_data.";
+
+const UTF8_BOM: &str = "\u{FEFF}";
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Column {
+    Number,
+    VehicleClass,
+    TypeCode,
+    InServiceSince,
+    OutOfServiceSince,
+    Manufacturer,
+    Depot,
+    PowerSources,
+    FixedCoupling,
+    OtherData(String),
+    Ignored,
+}
+impl Column {
+    fn from_header(header: &str) -> Option<Self> {
+        if header.is_empty() {
+            // spreadsheets like to append nameless columns
+            return Some(Self::Ignored);
+        }
+        if header.len() > OTHER_DATA_PREFIX.len() && header.is_char_boundary(OTHER_DATA_PREFIX.len()) {
+            let (prefix, key) = header.split_at(OTHER_DATA_PREFIX.len());
+            if prefix.eq_ignore_ascii_case(OTHER_DATA_PREFIX) {
+                return Some(Self::OtherData(key.to_owned()));
+            }
+        }
+
+        let normalized = header
+            .to_lowercase()
+            .replace(['-', ' '], "_");
+        match normalized.as_str() {
+            "number"|"veh_number"|"vehicle_number" => Some(Self::Number),
+            "vehicle_class"|"veh_class"|"class" => Some(Self::VehicleClass),
+            "type_code"|"type" => Some(Self::TypeCode),
+            "in_service_since" => Some(Self::InServiceSince),
+            "out_of_service_since" => Some(Self::OutOfServiceSince),
+            "manufacturer" => Some(Self::Manufacturer),
+            "depot" => Some(Self::Depot),
+            "power_sources"|"power_source" => Some(Self::PowerSources),
+            "fixed_coupling"|"coupling" => Some(Self::FixedCoupling),
+            _ => None,
+        }
+    }
+}
+
+
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split(LIST_SEPARATOR)
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.to_owned())
+}
+
+/// Encodes a value of `other_data` as a CSV cell.
+///
+/// Strings are written verbatim unless they would be read back as something else (a number, a
+/// boolean, JSON in general, or a string with surrounding whitespace, which is trimmed); those and
+/// all other values are written as JSON. An empty cell stands for a missing key.
+fn other_data_to_cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => {
+            let ambiguous = s.is_empty()
+                || s.trim() != s
+                || serde_json::from_str::<serde_json::Value>(s).is_ok();
+            if ambiguous {
+                value.to_string()
+            } else {
+                s.clone()
+            }
+        },
+        other => other.to_string(),
+    }
+}
+
+/// Decodes a CSV cell written by [`other_data_to_cell`]: cells containing valid JSON are parsed,
+/// all others are taken as strings.
+fn other_data_from_cell(cell: &str) -> serde_json::Value {
+    serde_json::from_str(cell)
+        .unwrap_or_else(|_| serde_json::Value::String(cell.to_owned()))
+}
+
+/// Guesses the field delimiter from the header line.
+///
+/// Depending on the locale, spreadsheet software separates fields with commas, semicolons or
+/// tabs.
+fn guess_delimiter(text: &str) -> u8 {
+    let mut counts: BTreeMap<u8, usize> = BTreeMap::new();
+    let mut in_quotes = false;
+    for b in text.bytes() {
+        match b {
+            b'"' => in_quotes = !in_quotes,
+            b'\n' if !in_quotes => break,
+            b',' | b';' | b'\t' if !in_quotes => *counts.entry(b).or_insert(0) += 1,
+            _ => {},
+        }
+    }
+    counts.into_iter()
+        .filter(|(_b, count)| *count > 0)
+        .max_by_key(|(b, count)| (*count, *b == b','))
+        .map(|(b, _count)| b)
+        .unwrap_or(b',')
+}
+
+
+/// Serializes vehicles into CSV.
+///
+/// The output starts with a byte-order mark so that spreadsheet software recognizes it as UTF-8.
+pub(crate) fn vehicles_to_csv(vehicles: &[ExportedVehicle]) -> Result<Vec<u8>, csv::Error> {
+    let other_data_keys: BTreeSet<&str> = vehicles.iter()
+        .filter_map(|v| v.other_data.as_object())
+        .flat_map(|od| od.keys())
+        .map(|k| k.as_str())
+        .collect();
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(UTF8_BOM.as_bytes());
+    {
+        let mut writer = csv::Writer::from_writer(&mut buf);
+
+        let mut header = vec![
+            "number".to_owned(), "vehicle_class".to_owned(), "type_code".to_owned(),
+            "in_service_since".to_owned(), "out_of_service_since".to_owned(),
+            "manufacturer".to_owned(), "depot".to_owned(),
+            "power_sources".to_owned(), "fixed_coupling".to_owned(),
+        ];
+        for key in &other_data_keys {
+            header.push(format!("{}{}", OTHER_DATA_PREFIX, key));
+        }
+        writer.write_record(&header)?;
+
+        let list_separator = LIST_SEPARATOR.to_string();
+        for vehicle in vehicles {
+            let mut record = vec![
+                vehicle.number.clone(),
+                vehicle.vehicle_class.clone(),
+                vehicle.type_code.clone(),
+                vehicle.in_service_since.clone().unwrap_or_default(),
+                vehicle.out_of_service_since.clone().unwrap_or_default(),
+                vehicle.manufacturer.clone().unwrap_or_default(),
+                vehicle.depot.clone().unwrap_or_default(),
+                vehicle.power_sources.iter()
+                    .map(|ps| ps.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(&list_separator),
+                vehicle.fixed_coupling.join(&list_separator),
+            ];
+            for key in &other_data_keys {
+                let value = match vehicle.other_data.get(key) {
+                    None => String::new(),
+                    Some(v) => other_data_to_cell(v),
+                };
+                record.push(value);
+            }
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+    }
+    Ok(buf)
+}
+
+
+/// Deserializes vehicles from CSV.
+///
+/// Columns are mapped by their header; `other_data.KEY` columns are collected into `other_data`
+/// (cells containing JSON as the value they encode, all others as strings) and list-valued columns
+/// are split on `|`. A leading byte-order mark is skipped and
+/// the field delimiter is guessed from the header line. Returns a human-readable description of the
+/// first problem encountered.
+pub(crate) fn vehicles_from_csv(data: &[u8]) -> Result<Vec<ExportedVehicle>, String> {
+    let text = match std::str::from_utf8(data) {
+        Ok(t) => t,
+        Err(e) => return Err(format!("CSV data is not valid UTF-8 ({}); please save the file with UTF-8 encoding", e)),
+    };
+    let text = text.strip_prefix(UTF8_BOM).unwrap_or(text);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(guess_delimiter(text))
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+
+    let headers = match reader.headers() {
+        Ok(h) => h.clone(),
+        Err(e) => return Err(format!("failed to read CSV header: {}", e)),
+    };
+    let mut columns = Vec::with_capacity(headers.len());
+    let mut seen_columns = BTreeSet::new();
+    for header in headers.iter() {
+        let column = match Column::from_header(header) {
+            Some(c) => c,
+            None => return Err(format!("unknown CSV column {:?}", header)),
+        };
+        if column != Column::Ignored && !seen_columns.insert(column.clone()) {
+            return Err(format!("duplicate CSV column {:?}", header));
+        }
+        columns.push(column);
+    }
+    for (required, name) in [(Column::Number, "number"), (Column::VehicleClass, "vehicle_class"), (Column::TypeCode, "type_code")] {
+        if !seen_columns.contains(&required) {
+            return Err(format!("required CSV column {:?} missing", name));
+        }
+    }
+
+    let mut vehicles = Vec::new();
+    for record_res in reader.records() {
+        let record = match record_res {
+            Ok(r) => r,
+            Err(e) => return Err(format!("failed to read CSV record: {}", e)),
+        };
+        if record.iter().all(|field| field.is_empty()) {
+            // empty line
+            continue;
+        }
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        if record.len() > columns.len() && record.iter().skip(columns.len()).any(|field| !field.is_empty()) {
+            return Err(format!("line {}: more values than columns", line));
+        }
+
+        let mut vehicle = ExportedVehicle {
+            number: String::new(),
+            vehicle_class: String::new(),
+            type_code: String::new(),
+            in_service_since: None,
+            out_of_service_since: None,
+            manufacturer: None,
+            depot: None,
+            other_data: serde_json::Value::Object(serde_json::Map::new()),
+            fixed_coupling: Vec::new(),
+            power_sources: BTreeSet::new(),
+        };
+        for (column, field) in columns.iter().zip(record.iter()) {
+            let optional_field = if field.is_empty() { None } else { Some(field.to_owned()) };
+            match column {
+                Column::Number => vehicle.number = field.to_owned(),
+                Column::VehicleClass => vehicle.vehicle_class = field.to_owned(),
+                Column::TypeCode => vehicle.type_code = field.to_owned(),
+                Column::InServiceSince => vehicle.in_service_since = optional_field,
+                Column::OutOfServiceSince => vehicle.out_of_service_since = optional_field,
+                Column::Manufacturer => vehicle.manufacturer = optional_field,
+                Column::Depot => vehicle.depot = optional_field,
+                Column::PowerSources => vehicle.power_sources = split_list(field).collect(),
+                Column::FixedCoupling => vehicle.fixed_coupling = split_list(field).collect(),
+                Column::OtherData(key) => {
+                    if !field.is_empty() {
+                        vehicle.other_data
+                            .as_object_mut().expect("other_data is an object")
+                            .insert(key.clone(), other_data_from_cell(field));
+                    }
+                },
+                Column::Ignored => {},
+            }
+        }
+        if vehicle.number.is_empty() {
+            return Err(format!("line {}: 'number' must not be empty", line));
+        }
+        vehicles.push(vehicle);
+    }
+
+    Ok(vehicles)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vehicle(number: &str, other_data: serde_json::Value) -> ExportedVehicle {
+        ExportedVehicle {
+            number: number.to_owned(),
+            vehicle_class: "tram".to_owned(),
+            type_code: "E1".to_owned(),
+            in_service_since: Some("1971".to_owned()),
+            out_of_service_since: None,
+            manufacturer: Some("Lohner".to_owned()),
+            depot: None,
+            other_data,
+            fixed_coupling: Vec::new(),
+            power_sources: ["overhead-line".to_owned()].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip_other_data() {
+        let vehicles = vec![
+            vehicle("4501", serde_json::json!({
+                "built": 1995,
+                "low_floor": true,
+                "seats": 42.5,
+                "names": ["Anna", "Berta"],
+                "extra": {"a": 1},
+                "nothing": null,
+                "plain": "hello, world",
+                "numeric_string": "1995",
+                "bool_string": "true",
+                "quoted": "\"quoted\"",
+                "padded": "  padded ",
+                "empty": "",
+            })),
+            vehicle("4502", serde_json::json!({"built": 1996})),
+        ];
+        let csv = vehicles_to_csv(&vehicles).unwrap();
+        let read_back = vehicles_from_csv(&csv).unwrap();
+        assert_eq!(read_back, vehicles);
+    }
+
+    #[test]
+    fn export_writes_plain_strings_verbatim() {
+        assert_eq!(other_data_to_cell(&serde_json::json!("hello")), "hello");
+        assert_eq!(other_data_to_cell(&serde_json::json!("1995")), "\"1995\"");
+        assert_eq!(other_data_to_cell(&serde_json::json!(1995)), "1995");
+        assert_eq!(other_data_from_cell("hello"), serde_json::json!("hello"));
+        assert_eq!(other_data_from_cell("1995"), serde_json::json!(1995));
+    }
+
+    #[test]
+    fn export_starts_with_bom() {
+        let csv = vehicles_to_csv(&[vehicle("1", serde_json::json!({}))]).unwrap();
+        assert!(csv.starts_with(UTF8_BOM.as_bytes()));
+    }
+
+    #[test]
+    fn import_skips_bom() {
+        let with_bom = "\u{FEFF}number,vehicle_class,type_code\n1,tram,E1\n";
+        let without_bom = "number,vehicle_class,type_code\n1,tram,E1\n";
+        let vehicles = vehicles_from_csv(with_bom.as_bytes()).unwrap();
+        assert_eq!(vehicles, vehicles_from_csv(without_bom.as_bytes()).unwrap());
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles[0].number, "1");
+    }
+
+    #[test]
+    fn import_rejects_invalid_utf8() {
+        let err = vehicles_from_csv(b"number,vehicle_class,type_code\n\xC4,tram,E1\n").unwrap_err();
+        assert!(err.contains("UTF-8"), "{}", err);
+    }
+
+    #[test]
+    fn guesses_delimiter() {
+        assert_eq!(guess_delimiter("number,vehicle_class,type_code\n"), b',');
+        assert_eq!(guess_delimiter("number;vehicle_class;type_code\n"), b';');
+        assert_eq!(guess_delimiter("number\tvehicle_class\ttype_code\n"), b'\t');
+        // delimiters within quotes and after the header line do not count
+        assert_eq!(guess_delimiter("\"a;b;c\",d\n1;2;3;4;5\n"), b',');
+        // ties and single columns fall back to commas
+        assert_eq!(guess_delimiter("a;b,c\n"), b',');
+        assert_eq!(guess_delimiter("number\n"), b',');
+    }
+
+    #[test]
+    fn imports_semicolon_separated() {
+        let data = "Number;Vehicle Class;Type;power_sources;other_data.built\n4501;tram;E1;overhead-line|battery;1995\n";
+        let vehicles = vehicles_from_csv(data.as_bytes()).unwrap();
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles[0].vehicle_class, "tram");
+        assert_eq!(vehicles[0].power_sources.len(), 2);
+        assert_eq!(vehicles[0].other_data, serde_json::json!({"built": 1995}));
+    }
+}
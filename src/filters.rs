@@ -8,9 +8,9 @@ pub(crate) fn url<S: AsRef<str>>(string: S, _runtime_values: &dyn askama::Values
     for c in s.chars() {
         // RFC3986: unreserved = ALPHA / DIGIT / "-" / "." / "_" / "~"
         let is_unreserved =
-            (c >= 'A' && c <= 'Z')
-            || (c >= 'a' && c <= 'z')
-            || (c >= '0' && c <= '9')
+            c.is_ascii_uppercase()
+            || c.is_ascii_lowercase()
+            || c.is_ascii_digit()
             || c == '-'
             || c == '.'
             || c == '_'
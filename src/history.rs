@@ -0,0 +1,805 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+
+use askama::Template;
+use http_body_util::Full;
+use hyper::{Method, Request, Response};
+use hyper::body::{Bytes, Incoming};
+use serde::Deserialize;
+use tokio_postgres::Transaction;
+use tokio_postgres::error::SqlState;
+use tracing::{error, instrument};
+
+use crate::{
+    db_connect, get_query_pairs, load_stored_vehicle, lock_coupling_company, lock_vehicle_company,
+    return_400, return_403, return_405, return_409, return_500, set_change_context, store_coupling,
+    store_vehicle, ExportedVehicle, StoreCouplingError,
+};
+use crate::auth;
+use crate::config;
+use crate::value_sets;
+
+
+/// Columns that identify the row and are therefore not shown as changes.
+const IDENTIFYING_COLUMNS: [&str; 3] = ["id", "bim_id", "coupling_id"];
+
+
+/// A column whose value differs between the old and the new row of a history entry.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct FieldChange {
+    pub column: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+/// An entry in the history of a vehicle or coupling.
+///
+/// `restorable` is set on the newest entry of each transaction except the latest one, as the state
+/// after a transaction is what can be restored.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct HistoryEntry {
+    pub id: i64,
+    pub changed_at: String,
+    pub description: String,
+    pub username: Option<String>,
+    pub token_id: Option<i64>,
+    pub remote_addr: Option<String>,
+    pub changes: Vec<FieldChange>,
+    pub restorable: bool,
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryTemplate {
+    pub base_path: String,
+    pub heading: String,
+    pub noun: &'static str,
+    pub edit_path: Option<String>,
+    pub restore_path: String,
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// A row of `bimdb.bims` as stored in the history.
+#[derive(Clone, Debug, Deserialize)]
+struct HistoricalVehicle {
+    pub company: String,
+    pub veh_number: String,
+    pub type_code: String,
+    pub veh_class: String,
+    pub in_service_since: Option<String>,
+    pub out_of_service_since: Option<String>,
+    pub manufacturer: Option<String>,
+    pub depot: Option<String>,
+    pub other_data: serde_json::Value,
+    #[serde(default)] pub deleted_at: Option<String>,
+}
+
+/// Which history to show.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum HistorySubject {
+    Vehicle,
+    Coupling,
+}
+
+
+fn display_value(value: Option<&serde_json::Value>) -> Option<String> {
+    match value {
+        None|Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(s)) => Some(s.clone()),
+        Some(other) => Some(other.to_string()),
+    }
+}
+
+/// Returns the columns whose values differ between the old and the new row, either of which may be
+/// missing.
+fn field_changes(old_row: Option<&serde_json::Value>, new_row: Option<&serde_json::Value>) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let old_map = old_row.and_then(|r| r.as_object()).unwrap_or(&empty);
+    let new_map = new_row.and_then(|r| r.as_object()).unwrap_or(&empty);
+
+    let mut columns: Vec<&String> = old_map.keys()
+        .chain(new_map.keys())
+        .filter(|c| !IDENTIFYING_COLUMNS.contains(&c.as_str()))
+        .collect();
+    columns.sort_unstable();
+    columns.dedup();
+
+    let mut changes = Vec::new();
+    for column in columns {
+        let old_value = display_value(old_map.get(column.as_str()));
+        let new_value = display_value(new_map.get(column.as_str()));
+        if old_value == new_value {
+            continue;
+        }
+        changes.push(FieldChange {
+            column: column.clone(),
+            old_value,
+            new_value,
+        });
+    }
+    changes
+}
+
+fn describe_vehicle_change(
+    table_name: &str,
+    operation: &str,
+    coupling_id: Option<i64>,
+    old_row: Option<&serde_json::Value>,
+    new_row: Option<&serde_json::Value>,
+) -> String {
+    let coupling_str = coupling_id
+        .map(|ci| ci.to_string())
+        .unwrap_or_else(|| "?".to_owned());
+    let was_deleted = display_value(old_row.and_then(|r| r.get("deleted_at"))).is_some();
+    let is_deleted = display_value(new_row.and_then(|r| r.get("deleted_at"))).is_some();
+    match (table_name, operation) {
+        ("bims", "INSERT") => "vehicle added".to_owned(),
+        ("bims", "UPDATE") if !was_deleted && is_deleted => "vehicle moved to the trash".to_owned(),
+        ("bims", "UPDATE") if was_deleted && !is_deleted => "vehicle restored from the trash".to_owned(),
+        ("bims", "UPDATE") => "vehicle changed".to_owned(),
+        ("bims", "DELETE") => "vehicle purged".to_owned(),
+        ("power_sources", "INSERT") => "power source added".to_owned(),
+        ("power_sources", "UPDATE") => "power source changed".to_owned(),
+        ("power_sources", "DELETE") => "power source removed".to_owned(),
+        ("coupling_bims", "INSERT") => format!("added to coupling {}", coupling_str),
+        ("coupling_bims", "UPDATE") => format!("moved within coupling {}", coupling_str),
+        ("coupling_bims", "DELETE") => format!("removed from coupling {}", coupling_str),
+        (table, op) => format!("{} on {}", op, table),
+    }
+}
+
+fn describe_coupling_change(table_name: &str, operation: &str, veh_number: Option<&str>) -> String {
+    let vehicle_str = veh_number.unwrap_or("?");
+    match (table_name, operation) {
+        ("couplings", "INSERT") => "coupling added".to_owned(),
+        ("couplings", "DELETE") => "coupling deleted".to_owned(),
+        ("coupling_bims", "INSERT") => format!("vehicle {} added", vehicle_str),
+        ("coupling_bims", "UPDATE") => format!("vehicle {} moved", vehicle_str),
+        ("coupling_bims", "DELETE") => format!("vehicle {} removed", vehicle_str),
+        (table, op) => format!("{} on {}", op, table),
+    }
+}
+
+fn get_id_param(query_pairs: &[(String, Option<String>)], key: &str) -> Result<i64, String> {
+    let value_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == key)
+        .flat_map(|(_k, v)| v)
+        .last();
+    let value = match value_opt {
+        Some(v) => v,
+        None => return Err(format!("missing parameter '{}'", key)),
+    };
+    value.parse()
+        .map_err(|_| format!("invalid parameter value for '{}'", key))
+}
+
+fn redirect_to(path: &str) -> Response<Full<Bytes>> {
+    let base_path = &config::current()
+        .http.base_path;
+    Response::builder()
+        .status(302)
+        .header("Location", format!("{}/{}", base_path, path))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("redirecting...")))
+        .unwrap_or_else(|_| return_500())
+}
+
+/// Handles the change history of a vehicle (including its power sources and coupling memberships)
+/// or of a coupling.
+///
+/// As the history contains user names and addresses, it is only shown to users who may edit the
+/// company of the vehicle or coupling.
+#[instrument(skip_all)]
+pub(crate) async fn handle_history(_remote_addr: SocketAddr, request: Request<Incoming>, subject: HistorySubject) -> Response<Full<Bytes>> {
+    if request.method() != Method::GET {
+        return return_405(request.method(), &[Method::GET]);
+    }
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return return_400("invalid UTF-8 in query"),
+    };
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    let id = match get_id_param(&query_pairs, "id") {
+        Ok(i) => i,
+        Err(e) => return return_400(&e),
+    };
+
+    let db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+
+    // purged vehicles and deleted couplings only live on in the history
+    let company_query = match subject {
+        HistorySubject::Vehicle => "
+            SELECT
+                EXISTS (SELECT 1 FROM bimdb.bims b WHERE b.id = $1 AND b.deleted_at IS NULL),
+                COALESCE(
+                    (SELECT b.company FROM bimdb.bims b WHERE b.id = $1),
+                    (
+                        SELECT COALESCE(h.new_row, h.old_row) ->> 'company'
+                        FROM bimdb.history h
+                        WHERE h.bim_id = $1 AND h.table_name = 'bims'
+                        ORDER BY h.id DESC
+                        LIMIT 1
+                    )
+                )
+        ",
+        HistorySubject::Coupling => "
+            SELECT
+                EXISTS (SELECT 1 FROM bimdb.couplings c WHERE c.id = $1),
+                COALESCE(
+                    (
+                        SELECT b.company
+                        FROM bimdb.coupling_bims cb
+                        INNER JOIN bimdb.bims b ON b.id = cb.bim_id
+                        WHERE cb.coupling_id = $1
+                        LIMIT 1
+                    ),
+                    (
+                        SELECT b.company
+                        FROM bimdb.history h
+                        INNER JOIN bimdb.bims b ON b.id = h.bim_id
+                        WHERE h.coupling_id = $1
+                        ORDER BY h.id DESC
+                        LIMIT 1
+                    ),
+                    (
+                        SELECT COALESCE(bh.new_row, bh.old_row) ->> 'company'
+                        FROM bimdb.history h
+                        INNER JOIN bimdb.history bh ON bh.bim_id = h.bim_id AND bh.table_name = 'bims'
+                        WHERE h.coupling_id = $1
+                        ORDER BY bh.id DESC
+                        LIMIT 1
+                    )
+                )
+        ",
+    };
+    let (exists, company): (bool, String) = match db_conn.query_one(company_query, &[&id]).await {
+        Ok(row) => match row.get(1) {
+            Some(c) => (row.get(0), c),
+            None => return return_400("failed to find this vehicle or coupling"),
+        },
+        Err(e) => {
+            error!("failed to obtain company of {:?} {}: {}", subject, id, e);
+            return return_500();
+        },
+    };
+    if !principal.may_edit(&company) {
+        return return_403(&format!("you may not view the history of company {:?}", company));
+    }
+
+    let entry_condition = match subject {
+        HistorySubject::Vehicle => "h.bim_id = $1",
+        HistorySubject::Coupling => "h.coupling_id = $1",
+    };
+    let entry_query = format!(
+        "
+            SELECT
+                h.id, to_char(h.changed_at, 'YYYY-MM-DD HH24:MI:SS TZ'), h.table_name, h.operation,
+                h.coupling_id, h.old_row, h.new_row, h.username, h.token_id, h.remote_addr,
+                h.transaction_id,
+                COALESCE(
+                    b.veh_number,
+                    (
+                        SELECT COALESCE(bh.new_row, bh.old_row) ->> 'veh_number'
+                        FROM bimdb.history bh
+                        WHERE bh.bim_id = h.bim_id AND bh.table_name = 'bims'
+                        ORDER BY bh.id DESC
+                        LIMIT 1
+                    )
+                )
+            FROM
+                bimdb.history h
+                LEFT OUTER JOIN bimdb.bims b ON b.id = h.bim_id
+            WHERE
+                {}
+            ORDER BY
+                h.id DESC
+        ",
+        entry_condition,
+    );
+    let entry_rows = match db_conn.query(&entry_query, &[&id]).await {
+        Ok(er) => er,
+        Err(e) => {
+            error!("failed to obtain history of {:?} {}: {}", subject, id, e);
+            return return_500();
+        },
+    };
+
+    let mut entries = Vec::with_capacity(entry_rows.len());
+    let mut newer_transaction_id: Option<i64> = None;
+    for row in entry_rows {
+        let table_name: String = row.get(2);
+        let operation: String = row.get(3);
+        let coupling_id: Option<i64> = row.get(4);
+        let old_row: Option<serde_json::Value> = row.get(5);
+        let new_row: Option<serde_json::Value> = row.get(6);
+        let transaction_id: i64 = row.get(10);
+        let veh_number: Option<String> = row.get(11);
+
+        let description = match subject {
+            HistorySubject::Vehicle => describe_vehicle_change(&table_name, &operation, coupling_id, old_row.as_ref(), new_row.as_ref()),
+            HistorySubject::Coupling => describe_coupling_change(&table_name, &operation, veh_number.as_deref()),
+        };
+        // deleted couplings are re-created when restored; purged vehicles cannot be restored
+        let restorable = (exists || subject == HistorySubject::Coupling)
+            && newer_transaction_id.is_some()
+            && newer_transaction_id != Some(transaction_id);
+        if newer_transaction_id.is_none() || restorable {
+            newer_transaction_id = Some(transaction_id);
+        }
+        entries.push(HistoryEntry {
+            id: row.get(0),
+            changed_at: row.get(1),
+            description,
+            username: row.get(7),
+            token_id: row.get(8),
+            remote_addr: row.get(9),
+            changes: field_changes(old_row.as_ref(), new_row.as_ref()),
+            restorable,
+        });
+    }
+
+    let (heading, noun, edit_path, restore_path) = match subject {
+        HistorySubject::Vehicle => (
+            format!("History of Vehicle {} ({})", id, company),
+            "vehicle",
+            format!("edit?id={}", id),
+            format!("restore?id={}", id),
+        ),
+        HistorySubject::Coupling => (
+            format!("History of Coupling {} ({})", id, company),
+            "coupling",
+            format!("coupling-edit?id={}", id),
+            format!("coupling-restore?id={}", id),
+        ),
+    };
+    let template = HistoryTemplate {
+        base_path: config::current().http.base_path.clone(),
+        heading,
+        noun,
+        edit_path: if exists { Some(edit_path) } else { None },
+        restore_path,
+        entries,
+    };
+    let template_text = template.render()
+        .expect("failed to render template");
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Full::new(Bytes::from(template_text)))
+        .unwrap_or_else(|_| return_500())
+}
+
+/// Returns the ID of the last history entry written by the same transaction as the given entry of
+/// the given vehicle or coupling, or `None` if there is no such entry.
+async fn revision_end(db_txn: &Transaction<'_>, subject: HistorySubject, id: i64, entry_id: i64) -> Result<Option<i64>, tokio_postgres::Error> {
+    let subject_column = match subject {
+        HistorySubject::Vehicle => "bim_id",
+        HistorySubject::Coupling => "coupling_id",
+    };
+    let query = format!(
+        "
+            SELECT MAX(h2.id)
+            FROM bimdb.history h
+            INNER JOIN bimdb.history h2
+                ON h2.transaction_id = h.transaction_id
+                AND h2.changed_at = h.changed_at
+                AND h2.{0} = h.{0}
+            WHERE h.id = $1
+            AND h.{0} = $2
+        ",
+        subject_column,
+    );
+    let row = db_txn.query_one(&query, &[&entry_id, &id]).await?;
+    Ok(row.get(0))
+}
+
+fn json_str(row: Option<&serde_json::Value>, key: &str) -> Option<String> {
+    row?.get(key)?.as_str().map(|s| s.to_owned())
+}
+
+fn json_i64(row: Option<&serde_json::Value>, key: &str) -> Option<i64> {
+    row?.get(key)?.as_i64()
+}
+
+/// Reconstructs the row and power sources of a vehicle after the given history entry by undoing all
+/// later changes. The row is `None` if the vehicle did not exist at that point.
+async fn vehicle_revision(db_txn: &Transaction<'_>, bim_id: i64, revision: i64) -> Result<(Option<serde_json::Value>, BTreeSet<String>), tokio_postgres::Error> {
+    let vehicle_row_opt = db_txn.query_opt(
+        "SELECT to_jsonb(b) FROM bimdb.bims b WHERE b.id = $1",
+        &[&bim_id],
+    ).await?;
+    let mut vehicle: Option<serde_json::Value> = vehicle_row_opt.map(|r| r.get(0));
+    let power_source_rows = db_txn.query(
+        "SELECT power_source FROM bimdb.power_sources WHERE bim_id = $1",
+        &[&bim_id],
+    ).await?;
+    let mut power_sources: BTreeSet<String> = power_source_rows.iter()
+        .map(|r| r.get(0))
+        .collect();
+
+    let later_rows = db_txn.query(
+        "
+            SELECT table_name, old_row, new_row
+            FROM bimdb.history
+            WHERE bim_id = $1
+            AND id > $2
+            AND table_name IN ('bims', 'power_sources')
+            ORDER BY id DESC
+        ",
+        &[&bim_id, &revision],
+    ).await?;
+    for later_row in later_rows {
+        let table_name: String = later_row.get(0);
+        let old_row: Option<serde_json::Value> = later_row.get(1);
+        let new_row: Option<serde_json::Value> = later_row.get(2);
+        if table_name == "bims" {
+            vehicle = old_row;
+        } else {
+            if let Some(new_power_source) = json_str(new_row.as_ref(), "power_source") {
+                power_sources.remove(&new_power_source);
+            }
+            if let Some(old_power_source) = json_str(old_row.as_ref(), "power_source") {
+                power_sources.insert(old_power_source);
+            }
+        }
+    }
+    Ok((vehicle, power_sources))
+}
+
+/// Reconstructs the vehicles of a coupling, in order, after the given history entry by undoing all
+/// later changes.
+async fn coupling_revision(db_txn: &Transaction<'_>, coupling_id: i64, revision: i64) -> Result<Vec<i64>, tokio_postgres::Error> {
+    let member_rows = db_txn.query(
+        "SELECT bim_id, position FROM bimdb.coupling_bims WHERE coupling_id = $1",
+        &[&coupling_id],
+    ).await?;
+    let mut bim_to_position: BTreeMap<i64, i64> = member_rows.iter()
+        .map(|r| (r.get(0), r.get(1)))
+        .collect();
+
+    let later_rows = db_txn.query(
+        "
+            SELECT old_row, new_row
+            FROM bimdb.history
+            WHERE coupling_id = $1
+            AND id > $2
+            AND table_name = 'coupling_bims'
+            ORDER BY id DESC
+        ",
+        &[&coupling_id, &revision],
+    ).await?;
+    for later_row in later_rows {
+        let old_row: Option<serde_json::Value> = later_row.get(0);
+        let new_row: Option<serde_json::Value> = later_row.get(1);
+        if let Some(new_bim_id) = json_i64(new_row.as_ref(), "bim_id") {
+            bim_to_position.remove(&new_bim_id);
+        }
+        if let (Some(old_bim_id), Some(old_position)) = (json_i64(old_row.as_ref(), "bim_id"), json_i64(old_row.as_ref(), "position")) {
+            bim_to_position.insert(old_bim_id, old_position);
+        }
+    }
+
+    let mut members: Vec<(i64, i64)> = bim_to_position.into_iter()
+        .map(|(bim_id, position)| (position, bim_id))
+        .collect();
+    members.sort_unstable();
+    Ok(members.into_iter().map(|(_position, bim_id)| bim_id).collect())
+}
+
+/// Restores a vehicle or coupling to the state after the transaction that wrote the given history
+/// entry. A deleted coupling is re-created with its previous ID. The restoration is itself recorded
+/// in the history.
+#[instrument(skip_all)]
+pub(crate) async fn handle_restore(remote_addr: SocketAddr, request: Request<Incoming>, subject: HistorySubject) -> Response<Full<Bytes>> {
+    if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::POST]);
+    }
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return return_400("invalid UTF-8 in query"),
+    };
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    let id = match get_id_param(&query_pairs, "id") {
+        Ok(i) => i,
+        Err(e) => return return_400(&e),
+    };
+    let entry_id = match get_id_param(&query_pairs, "revision") {
+        Ok(ei) => ei,
+        Err(e) => return return_400(&e),
+    };
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return return_500();
+        },
+    };
+    if let Err(e) = set_change_context(&db_txn, Some(&principal), Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return return_500();
+    }
+
+    // lock first so that the history does not change under our feet
+    let mut recreate_coupling = false;
+    let current_company_opt = match subject {
+        HistorySubject::Vehicle => match lock_vehicle_company(&db_txn, id).await {
+            Ok(Some(c)) => Some(c),
+            Ok(None) => return return_400("failed to find this vehicle"),
+            Err(e) => {
+                error!("failed to obtain company of vehicle {}: {}", id, e);
+                return return_500();
+            },
+        },
+        HistorySubject::Coupling => match lock_coupling_company(&db_txn, id).await {
+            Ok(Some(c)) => c,
+            Ok(None) => {
+                // the coupling has been deleted; it is re-created from its recorded vehicles
+                recreate_coupling = true;
+                None
+            },
+            Err(e) => {
+                error!("failed to obtain company of coupling {}: {}", id, e);
+                return return_500();
+            },
+        },
+    };
+    if let Some(current_company) = &current_company_opt {
+        if !principal.may_edit(current_company) {
+            return return_403(&format!("you may not edit company {:?}", current_company));
+        }
+    }
+
+    let revision = match revision_end(&db_txn, subject, id, entry_id).await {
+        Ok(Some(r)) => r,
+        Ok(None) => return return_400("failed to find this revision"),
+        Err(e) => {
+            error!("failed to find revision {} of {:?} {}: {}", entry_id, subject, id, e);
+            return return_500();
+        },
+    };
+
+    let redirect_path = match subject {
+        HistorySubject::Vehicle => {
+            let (vehicle_row_opt, power_sources) = match vehicle_revision(&db_txn, id, revision).await {
+                Ok(vr) => vr,
+                Err(e) => {
+                    error!("failed to reconstruct revision {} of vehicle {}: {}", revision, id, e);
+                    return return_500();
+                },
+            };
+            let Some(vehicle_row) = vehicle_row_opt else {
+                return return_400("the vehicle did not exist at this revision");
+            };
+            let historical: HistoricalVehicle = match serde_json::from_value(vehicle_row) {
+                Ok(h) => h,
+                Err(e) => {
+                    error!("failed to decode revision {} of vehicle {}: {}", revision, id, e);
+                    return return_500();
+                },
+            };
+            if historical.deleted_at.is_some() {
+                return return_400("the vehicle was in the trash at this revision");
+            }
+
+            // the value sets may have changed in the meantime
+            let value_sets = match value_sets::load_value_sets(&db_txn).await {
+                Some(vs) => vs,
+                None => return return_500(),
+            };
+            let mut vehicle = ExportedVehicle {
+                number: historical.veh_number,
+                vehicle_class: historical.veh_class,
+                type_code: historical.type_code,
+                in_service_since: historical.in_service_since,
+                out_of_service_since: historical.out_of_service_since,
+                manufacturer: historical.manufacturer,
+                depot: historical.depot,
+                other_data: historical.other_data,
+                fixed_coupling: Vec::with_capacity(0),
+                power_sources,
+            };
+            if let Err(e) = ExportedVehicle::validate_company(&historical.company) {
+                return return_400(&format!("this revision is no longer valid: {}", e));
+            }
+            let stored = match load_stored_vehicle(&db_txn, id, &historical.company).await {
+                Some(s) => s,
+                None => return return_500(),
+            };
+            if let Err(e) = vehicle.validate(&historical.company, &value_sets, stored.as_ref()) {
+                return return_400(&format!("this revision is no longer valid: {}", e));
+            }
+            if !principal.may_edit(&historical.company) {
+                return return_403(&format!("you may not edit vehicles of company {:?}", historical.company));
+            }
+
+            match store_vehicle(&db_txn, Some(id), &historical.company, &vehicle).await {
+                Ok(Some(_bim_id)) => {},
+                Ok(None) => return return_400("failed to find this vehicle"),
+                Err(e) => {
+                    if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                        return return_409("another vehicle of this company now has this number");
+                    }
+                    error!("failed to restore revision {} of vehicle {}: {}", revision, id, e);
+                    return return_500();
+                },
+            }
+            format!("history?id={}", id)
+        },
+        HistorySubject::Coupling => {
+            let vehicle_ids = match coupling_revision(&db_txn, id, revision).await {
+                Ok(vi) => vi,
+                Err(e) => {
+                    error!("failed to reconstruct revision {} of coupling {}: {}", revision, id, e);
+                    return return_500();
+                },
+            };
+            if vehicle_ids.is_empty() {
+                return return_400("the coupling had no vehicles at this revision");
+            }
+
+            let company_row_res = db_txn.query_opt(
+                "SELECT company FROM bimdb.bims WHERE id = $1",
+                &[&vehicle_ids[0]],
+            ).await;
+            match company_row_res {
+                Ok(Some(row)) => {
+                    let company: String = row.get(0);
+                    if !principal.may_edit(&company) {
+                        return return_403(&format!("you may not edit couplings of company {:?}", company));
+                    }
+                },
+                Ok(None) => {
+                    // store_coupling reports the missing vehicle
+                },
+                Err(e) => {
+                    error!("failed to obtain company of vehicle {}: {}", vehicle_ids[0], e);
+                    return return_500();
+                },
+            }
+
+            if recreate_coupling {
+                // keep the ID so that the history continues
+                if let Err(e) = db_txn.execute("INSERT INTO bimdb.couplings (id) VALUES ($1)", &[&id]).await {
+                    if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                        return return_409("this coupling has been re-created in the meantime");
+                    }
+                    error!("failed to re-create coupling {}: {}", id, e);
+                    return return_500();
+                }
+            }
+
+            match store_coupling(&db_txn, Some(id), &vehicle_ids).await {
+                Ok(Some(_coupling_id)) => {},
+                Ok(None) => return return_400("failed to find this coupling"),
+                Err(StoreCouplingError::DuplicateVehicle(_)) => return return_400("a vehicle is listed more than once"),
+                Err(StoreCouplingError::TooFewVehicles) => return return_400("a coupling must contain at least two vehicles"),
+                Err(StoreCouplingError::UnknownVehicle(vehicle_id)) => {
+                    return return_400(&format!(
+                        "this version cannot be restored as vehicle {} has since been moved to the trash or purged",
+                        vehicle_id,
+                    ));
+                },
+                Err(StoreCouplingError::MixedCompanies(_, _)) => {
+                    return return_400("the vehicles of this revision now belong to different companies");
+                },
+                Err(StoreCouplingError::AlreadyCoupled { veh_number, coupling_id, .. }) => {
+                    return return_409(&format!("vehicle {} is now part of coupling {}", veh_number, coupling_id));
+                },
+                Err(StoreCouplingError::Database(e)) => {
+                    error!("failed to restore revision {} of coupling {}: {}", revision, id, e);
+                    return return_500();
+                },
+            }
+            format!("coupling-history?id={}", id)
+        },
+    };
+
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit restoration transaction: {}", e);
+        return return_500();
+    }
+
+    redirect_to(&redirect_path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn change(column: &str, old_value: Option<&str>, new_value: Option<&str>) -> FieldChange {
+        FieldChange {
+            column: column.to_owned(),
+            old_value: old_value.map(|v| v.to_owned()),
+            new_value: new_value.map(|v| v.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_field_changes_insert() {
+        let new_row = json!({"id": 4, "company": "Graz", "veh_number": "501", "depot": null, "other_data": {"a": 1}});
+        assert_eq!(
+            field_changes(None, Some(&new_row)),
+            [
+                change("company", None, Some("Graz")),
+                change("other_data", None, Some("{\"a\":1}")),
+                change("veh_number", None, Some("501")),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_field_changes_update() {
+        let old_row = json!({"id": 4, "company": "Graz", "veh_number": "501", "depot": null, "in_service_since": "1990"});
+        let new_row = json!({"id": 4, "company": "Graz", "veh_number": "502", "depot": "Steyrergasse", "in_service_since": null});
+        assert_eq!(
+            field_changes(Some(&old_row), Some(&new_row)),
+            [
+                change("depot", None, Some("Steyrergasse")),
+                change("in_service_since", Some("1990"), None),
+                change("veh_number", Some("501"), Some("502")),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_field_changes_delete() {
+        let old_row = json!({"bim_id": 4, "coupling_id": 2, "position": 1});
+        assert_eq!(
+            field_changes(Some(&old_row), None),
+            [change("position", Some("1"), None)],
+        );
+    }
+
+    #[test]
+    fn test_field_changes_unchanged() {
+        let row = json!({"id": 4, "company": "Graz", "depot": null, "other_data": {}});
+        assert_eq!(field_changes(Some(&row), Some(&row)), []);
+        assert_eq!(field_changes(None, None), []);
+
+        // a missing column and a null value both display as empty
+        let old_row = json!({"id": 4, "company": "Graz"});
+        let new_row = json!({"id": 5, "company": "Graz", "depot": null});
+        assert_eq!(field_changes(Some(&old_row), Some(&new_row)), []);
+    }
+
+    #[test]
+    fn test_describe_vehicle_change() {
+        let live = json!({"deleted_at": null});
+        let trashed = json!({"deleted_at": "2024-01-01 00:00:00+00"});
+        assert_eq!(describe_vehicle_change("bims", "INSERT", None, None, Some(&live)), "vehicle added");
+        assert_eq!(describe_vehicle_change("bims", "UPDATE", None, Some(&live), Some(&live)), "vehicle changed");
+        assert_eq!(describe_vehicle_change("bims", "UPDATE", None, Some(&live), Some(&trashed)), "vehicle moved to the trash");
+        assert_eq!(describe_vehicle_change("bims", "UPDATE", None, Some(&trashed), Some(&live)), "vehicle restored from the trash");
+        assert_eq!(describe_vehicle_change("bims", "DELETE", None, Some(&trashed), None), "vehicle purged");
+        assert_eq!(describe_vehicle_change("power_sources", "INSERT", None, None, None), "power source added");
+        assert_eq!(describe_vehicle_change("power_sources", "DELETE", None, None, None), "power source removed");
+        assert_eq!(describe_vehicle_change("coupling_bims", "INSERT", Some(7), None, None), "added to coupling 7");
+        assert_eq!(describe_vehicle_change("coupling_bims", "UPDATE", Some(7), None, None), "moved within coupling 7");
+        assert_eq!(describe_vehicle_change("coupling_bims", "DELETE", None, None, None), "removed from coupling ?");
+        assert_eq!(describe_vehicle_change("bims", "TRUNCATE", None, None, None), "TRUNCATE on bims");
+    }
+
+    #[test]
+    fn test_describe_coupling_change() {
+        assert_eq!(describe_coupling_change("couplings", "INSERT", None), "coupling added");
+        assert_eq!(describe_coupling_change("couplings", "DELETE", None), "coupling deleted");
+        assert_eq!(describe_coupling_change("coupling_bims", "INSERT", Some("4711")), "vehicle 4711 added");
+        assert_eq!(describe_coupling_change("coupling_bims", "UPDATE", Some("4711")), "vehicle 4711 moved");
+        assert_eq!(describe_coupling_change("coupling_bims", "DELETE", None), "vehicle ? removed");
+        assert_eq!(describe_coupling_change("couplings", "UPDATE", None), "UPDATE on couplings");
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Transaction;
+
+use crate::ExportedVehicle;
+use crate::value_sets::ValueSets;
+
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct ImportSummary {
+    pub inserted: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+    pub couplings: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VehicleAction {
+    Insert,
+    Update,
+    Unchanged,
+}
+
+/// A change to a single field of a vehicle; `null` stands for an absent value.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+impl FieldChange {
+    fn value_text(value: &serde_json::Value) -> String {
+        match value {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Array(items) => items.iter()
+                .map(Self::value_text)
+                .collect::<Vec<String>>()
+                .join(", "),
+            other => other.to_string(),
+        }
+    }
+
+    pub fn old_text(&self) -> String { Self::value_text(&self.old) }
+    pub fn new_text(&self) -> String { Self::value_text(&self.new) }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct VehicleChange {
+    pub number: String,
+    pub action: VehicleAction,
+    pub changes: Vec<FieldChange>,
+}
+
+/// The changes an import would make to the database.
+///
+/// `vehicles` is in the same order as the imported vehicles; `uncoupled` lists the numbers of
+/// vehicles which are not part of the import but lose their coupling.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct ImportPlan {
+    pub company: String,
+    pub vehicles: Vec<VehicleChange>,
+    pub uncoupled: Vec<String>,
+}
+impl ImportPlan {
+    pub fn count(&self, action: VehicleAction) -> usize {
+        self.vehicles.iter()
+            .filter(|v| v.action == action)
+            .count()
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.vehicles.iter().any(|v| v.action != VehicleAction::Unchanged)
+    }
+}
+
+
+/// Validates a list of vehicles to be imported for the given company, whose vehicles are currently
+/// stored as `stored_vehicles`.
+///
+/// Empty optional values are normalized to `None`. Returns a human-readable description of the
+/// first problem encountered.
+pub(crate) fn validate_import(company: &str, vehicles: &mut [ExportedVehicle], value_sets: &ValueSets, stored_vehicles: &[ExportedVehicle]) -> Result<(), String> {
+    ExportedVehicle::validate_company(company)?;
+
+    let number_to_stored: BTreeMap<&str, &ExportedVehicle> = stored_vehicles.iter()
+        .map(|v| (v.number.as_str(), v))
+        .collect();
+    let mut seen_numbers = HashSet::new();
+    for vehicle in vehicles.iter_mut() {
+        let stored = number_to_stored.get(vehicle.number.as_str()).copied();
+        if let Err(e) = vehicle.validate(company, value_sets, stored) {
+            return Err(format!("vehicle {:?}: {}", vehicle.number, e));
+        }
+        if !seen_numbers.insert(vehicle.number.clone()) {
+            return Err(format!("vehicle {:?} appears multiple times", vehicle.number));
+        }
+    }
+
+    // couplings must be consistent: every member lists the same vehicles in the same order
+    let number_to_coupling: BTreeMap<&str, &[String]> = vehicles.iter()
+        .map(|v| (v.number.as_str(), v.fixed_coupling.as_slice()))
+        .collect();
+    for vehicle in vehicles.iter() {
+        if vehicle.fixed_coupling.is_empty() {
+            continue;
+        }
+        if !vehicle.fixed_coupling.contains(&vehicle.number) {
+            return Err(format!("vehicle {:?}: 'fixed_coupling' does not contain the vehicle itself", vehicle.number));
+        }
+        let mut seen_members = HashSet::new();
+        for member in &vehicle.fixed_coupling {
+            if !seen_members.insert(member.as_str()) {
+                return Err(format!("vehicle {:?}: {:?} appears multiple times in 'fixed_coupling'", vehicle.number, member));
+            }
+            match number_to_coupling.get(member.as_str()) {
+                Some(member_coupling) => {
+                    if *member_coupling != vehicle.fixed_coupling.as_slice() {
+                        return Err(format!("vehicle {:?}: 'fixed_coupling' differs from that of coupled vehicle {:?}", vehicle.number, member));
+                    }
+                },
+                None => return Err(format!("vehicle {:?}: coupled vehicle {:?} is not part of the import", vehicle.number, member)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+/// The fields of a vehicle (apart from its number and other data) in the order in which they are
+/// compared.
+const COMPARED_FIELDS: [&str; 8] = [
+    "vehicle_class", "type_code", "in_service_since", "out_of_service_since",
+    "manufacturer", "depot", "power_sources", "fixed_coupling",
+];
+
+/// Fields which are stored in `bimdb.bims` itself.
+const ROW_FIELDS: [&str; 6] = [
+    "vehicle_class", "type_code", "in_service_since", "out_of_service_since",
+    "manufacturer", "depot",
+];
+
+fn is_empty_value(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => true,
+        serde_json::Value::Array(a) => a.is_empty(),
+        serde_json::Value::Object(o) => o.is_empty(),
+        _ => false,
+    }
+}
+
+fn diff_vehicles(old: Option<&ExportedVehicle>, new: &ExportedVehicle) -> Vec<FieldChange> {
+    let empty_map = serde_json::Map::new();
+    let old_value = old.map(|o| serde_json::to_value(o).expect("failed to convert vehicle to JSON"));
+    let old_map = old_value.as_ref()
+        .and_then(|ov| ov.as_object())
+        .unwrap_or(&empty_map);
+    let new_value = serde_json::to_value(new).expect("failed to convert vehicle to JSON");
+    let new_map = new_value.as_object()
+        .expect("vehicle not converted to JSON object");
+
+    let mut changes = Vec::new();
+    for field in COMPARED_FIELDS {
+        let old_field = old_map.get(field).unwrap_or(&serde_json::Value::Null);
+        let new_field = new_map.get(field).unwrap_or(&serde_json::Value::Null);
+        let differs = if old.is_some() {
+            old_field != new_field
+        } else {
+            !is_empty_value(new_field)
+        };
+        if differs {
+            changes.push(FieldChange {
+                field: field.to_owned(),
+                old: old_field.clone(),
+                new: new_field.clone(),
+            });
+        }
+    }
+
+    let old_other = old.and_then(|o| o.other_data.as_object()).unwrap_or(&empty_map);
+    let new_other = new.other_data.as_object().unwrap_or(&empty_map);
+    let other_keys: BTreeSet<&String> = old_other.keys()
+        .chain(new_other.keys())
+        .collect();
+    for key in other_keys {
+        let old_field = old_other.get(key);
+        let new_field = new_other.get(key);
+        if old_field != new_field {
+            changes.push(FieldChange {
+                field: format!("other_data.{}", key),
+                old: old_field.cloned().unwrap_or(serde_json::Value::Null),
+                new: new_field.cloned().unwrap_or(serde_json::Value::Null),
+            });
+        }
+    }
+
+    changes
+}
+
+
+/// Locks the vehicle and coupling tables against concurrent modification for the rest of the
+/// transaction, ensuring that a plan remains valid until it has been applied.
+pub(crate) async fn lock_for_import(db_txn: &Transaction<'_>) -> Result<(), tokio_postgres::Error> {
+    db_txn.execute(
+        "LOCK TABLE bimdb.bims, bimdb.power_sources, bimdb.couplings, bimdb.coupling_bims IN SHARE ROW EXCLUSIVE MODE",
+        &[],
+    ).await?;
+    Ok(())
+}
+
+
+/// Calculates the changes that importing the given (previously validated) vehicles for the given
+/// company would make.
+pub(crate) async fn plan_import<C: GenericClient>(db_conn: &C, company: &str, vehicles: &[ExportedVehicle]) -> Option<ImportPlan> {
+    let existing_vehicles = crate::load_company_vehicles(db_conn, company).await?;
+    let number_to_existing: BTreeMap<&str, &ExportedVehicle> = existing_vehicles.iter()
+        .map(|v| (v.number.as_str(), v))
+        .collect();
+    let imported_numbers: HashSet<&str> = vehicles.iter()
+        .map(|v| v.number.as_str())
+        .collect();
+
+    let mut vehicle_changes = Vec::with_capacity(vehicles.len());
+    let mut uncoupled = BTreeSet::new();
+    for vehicle in vehicles {
+        let existing = number_to_existing.get(vehicle.number.as_str()).copied();
+        let changes = diff_vehicles(existing, vehicle);
+        let action = match existing {
+            None => VehicleAction::Insert,
+            Some(_) if !changes.is_empty() => VehicleAction::Update,
+            Some(_) => VehicleAction::Unchanged,
+        };
+
+        if let Some(ev) = existing {
+            if ev.fixed_coupling != vehicle.fixed_coupling {
+                // the old coupling is dissolved; members outside of the import end up uncoupled
+                for member in &ev.fixed_coupling {
+                    if !imported_numbers.contains(member.as_str()) {
+                        uncoupled.insert(member.clone());
+                    }
+                }
+            }
+        }
+
+        vehicle_changes.push(VehicleChange {
+            number: vehicle.number.clone(),
+            action,
+            changes,
+        });
+    }
+
+    Some(ImportPlan {
+        company: company.to_owned(),
+        vehicles: vehicle_changes,
+        uncoupled: uncoupled.into_iter().collect(),
+    })
+}
+
+
+/// Applies a plan obtained from [`plan_import`] for the same vehicles.
+///
+/// Vehicles are inserted or updated by number, their power sources are replaced and every coupling
+/// whose composition changes is replaced by the coupling given in the import. Unchanged vehicles
+/// are not touched.
+pub(crate) async fn apply_import(db_txn: &Transaction<'_>, vehicles: &[ExportedVehicle], plan: &ImportPlan) -> Result<ImportSummary, tokio_postgres::Error> {
+    assert_eq!(vehicles.len(), plan.vehicles.len());
+    let company = plan.company.as_str();
+    let mut summary = ImportSummary::default();
+
+    let upsert_stmt = db_txn.prepare(
+        "
+            INSERT INTO bimdb.bims
+                (
+                    id,
+                    company, veh_number, type_code, veh_class,
+                    in_service_since, out_of_service_since, manufacturer, depot,
+                    other_data
+                )
+            VALUES
+                (
+                    DEFAULT,
+                    $1, $2, $3, $4,
+                    $5, $6, $7, $8,
+                    $9
+                )
+            ON CONFLICT (company, veh_number) WHERE deleted_at IS NULL DO UPDATE
+            SET
+                type_code = EXCLUDED.type_code,
+                veh_class = EXCLUDED.veh_class,
+                in_service_since = EXCLUDED.in_service_since,
+                out_of_service_since = EXCLUDED.out_of_service_since,
+                manufacturer = EXCLUDED.manufacturer,
+                depot = EXCLUDED.depot,
+                other_data = EXCLUDED.other_data
+            RETURNING id
+        ",
+    ).await?;
+    let select_id_stmt = db_txn.prepare(
+        "SELECT id FROM bimdb.bims WHERE company = $1 AND veh_number = $2 AND deleted_at IS NULL",
+    ).await?;
+    let delete_power_sources_stmt = db_txn.prepare(
+        "DELETE FROM bimdb.power_sources WHERE bim_id = $1",
+    ).await?;
+    let insert_power_source_stmt = db_txn.prepare(
+        "INSERT INTO bimdb.power_sources (bim_id, power_source) VALUES ($1, $2)",
+    ).await?;
+
+    let mut recoupled_vehicles: Vec<&ExportedVehicle> = Vec::new();
+    let mut number_to_id: BTreeMap<&str, i64> = BTreeMap::new();
+    for (vehicle, vehicle_change) in vehicles.iter().zip(plan.vehicles.iter()) {
+        match vehicle_change.action {
+            VehicleAction::Insert => summary.inserted += 1,
+            VehicleAction::Update => summary.updated += 1,
+            VehicleAction::Unchanged => {
+                summary.unchanged += 1;
+                continue;
+            },
+        }
+
+        let row_changed = vehicle_change.changes.iter()
+            .any(|c| ROW_FIELDS.contains(&c.field.as_str()) || c.field.starts_with("other_data."));
+        let power_sources_changed = vehicle_change.changes.iter()
+            .any(|c| c.field == "power_sources");
+        let coupling_changed = vehicle_change.changes.iter()
+            .any(|c| c.field == "fixed_coupling");
+
+        let bim_id: i64 = if row_changed || vehicle_change.action == VehicleAction::Insert {
+            let row = db_txn.query_one(
+                &upsert_stmt,
+                &[
+                    &company, &vehicle.number, &vehicle.type_code, &vehicle.vehicle_class,
+                    &vehicle.in_service_since, &vehicle.out_of_service_since, &vehicle.manufacturer, &vehicle.depot,
+                    &vehicle.other_data,
+                ],
+            ).await?;
+            row.get(0)
+        } else {
+            let row = db_txn.query_one(&select_id_stmt, &[&company, &vehicle.number]).await?;
+            row.get(0)
+        };
+        number_to_id.insert(vehicle.number.as_str(), bim_id);
+
+        if power_sources_changed {
+            db_txn.execute(&delete_power_sources_stmt, &[&bim_id]).await?;
+            for power_source in &vehicle.power_sources {
+                db_txn.execute(&insert_power_source_stmt, &[&bim_id, &power_source.as_str()]).await?;
+            }
+        }
+
+        if coupling_changed {
+            recoupled_vehicles.push(vehicle);
+        }
+    }
+
+    if recoupled_vehicles.is_empty() {
+        return Ok(summary);
+    }
+
+    // dissolve the existing couplings of the vehicles whose coupling changes
+    let recoupled_ids: Vec<i64> = recoupled_vehicles.iter()
+        .map(|v| number_to_id[v.number.as_str()])
+        .collect();
+    let coupling_id_rows = db_txn.query(
+        "SELECT DISTINCT coupling_id FROM bimdb.coupling_bims WHERE bim_id = ANY($1)",
+        &[&recoupled_ids],
+    ).await?;
+    let old_coupling_ids: Vec<i64> = coupling_id_rows.iter()
+        .map(|r| r.get(0))
+        .collect();
+    if !old_coupling_ids.is_empty() {
+        db_txn.execute(
+            "DELETE FROM bimdb.coupling_bims WHERE coupling_id = ANY($1)",
+            &[&old_coupling_ids],
+        ).await?;
+        db_txn.execute(
+            "DELETE FROM bimdb.couplings WHERE id = ANY($1)",
+            &[&old_coupling_ids],
+        ).await?;
+    }
+
+    // create the new couplings
+    let insert_coupling_bim_stmt = db_txn.prepare(
+        "INSERT INTO bimdb.coupling_bims (bim_id, coupling_id, position) VALUES ($1, $2, $3)",
+    ).await?;
+    let mut created_couplings: BTreeSet<&[String]> = BTreeSet::new();
+    for vehicle in recoupled_vehicles {
+        if vehicle.fixed_coupling.is_empty() {
+            continue;
+        }
+        if !created_couplings.insert(vehicle.fixed_coupling.as_slice()) {
+            // already created while processing another member
+            continue;
+        }
+
+        let coupling_row = db_txn.query_one(
+            "INSERT INTO bimdb.couplings (id) VALUES (DEFAULT) RETURNING id",
+            &[],
+        ).await?;
+        let coupling_id: i64 = coupling_row.get(0);
+        for (i, member) in vehicle.fixed_coupling.iter().enumerate() {
+            let position: i64 = (i + 1).try_into().unwrap();
+            // all members of a changed coupling have changed as well, so their IDs are known
+            let member_id = number_to_id[member.as_str()];
+            db_txn.execute(&insert_coupling_bim_stmt, &[&member_id, &coupling_id, &position]).await?;
+        }
+        summary.couplings += 1;
+    }
+
+    Ok(summary)
+}
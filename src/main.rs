@@ -1,38 +1,63 @@
+mod api;
+mod auth;
+mod cli;
 mod config;
+mod csv_vehicles;
 mod filters;
+mod history;
+mod import;
+mod migrations;
+mod natural_sort;
+mod search;
+mod trash;
+mod users;
 mod value_multiset;
+mod value_sets;
 
 
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::Infallible;
-use std::ffi::OsString;
-use std::fs::File;
-use std::io::Read;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::process::ExitCode;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use askama::Template;
-use ciborium::cbor;
-use form_urlencoded;
+use clap::Parser;
+use deadpool_postgres::{
+    GenericClient, Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod, Runtime, TimeoutType,
+};
 use http_body_util::{BodyExt, Full};
+use http_body_util::combinators::BoxBody;
 use hyper::{Method, Request, Response};
-use hyper::body::{Bytes, Incoming};
+use hyper::body::{Bytes, Frame, Incoming};
 use hyper::service::service_fn;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
-use percent_encoding;
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_postgres::{IsolationLevel, Transaction};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::error::SqlState;
 use tokio_postgres::types::ToSql;
-use toml;
-use tracing::{error, instrument, warn};
-use tracing_subscriber;
-
-use crate::config::{CONFIG, Config};
+use tracing::{error, info, instrument, warn};
+
+use crate::auth::{Principal, RequiredAccess, ViewScope};
+use crate::cli::{Cli, Command};
+use crate::config::{DbConfig, DbSslMode};
+use crate::history::HistorySubject;
+use crate::import::{ImportPlan, VehicleAction};
+use crate::search::{MatchMode, VehicleFilter, VehicleSort};
+use crate::value_sets::{AllowedValue, ValueOption, ValueSet, ValueSets};
 use crate::value_multiset::ValueMultiset;
 
 
@@ -45,6 +70,7 @@ static STATIC_FILE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(concat!(
     ")*",
     "$",
 )).expect("failed to compile static file regex"));
+static DB_POOL: OnceLock<Pool> = OnceLock::new();
 
 
 #[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -58,12 +84,14 @@ struct BimPart {
     pub out_of_service_since: Option<String>,
     pub manufacturer: Option<String>,
     pub depot: Option<String>,
+    pub editable: bool,
 }
 
 #[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 struct CouplingPart {
     pub id: i64,
     pub vehicles: Vec<CouplingVehiclePart>,
+    pub editable: bool,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -72,6 +100,161 @@ struct CouplingVehiclePart {
     pub veh_number: String,
 }
 
+/// A vehicle in the format used by the exports and imports.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct ExportedVehicle {
+    pub number: String,
+    pub vehicle_class: String,
+    pub type_code: String,
+    #[serde(default)] pub in_service_since: Option<String>,
+    #[serde(default)] pub out_of_service_since: Option<String>,
+    #[serde(default)] pub manufacturer: Option<String>,
+    #[serde(default)] pub depot: Option<String>,
+    #[serde(default = "ExportedVehicle::default_other_data")] pub other_data: serde_json::Value,
+    #[serde(default)] pub fixed_coupling: Vec<String>,
+    #[serde(default)] pub power_sources: BTreeSet<String>,
+}
+impl ExportedVehicle {
+    fn default_other_data() -> serde_json::Value { serde_json::Value::Object(serde_json::Map::new()) }
+
+    /// Checks a company name against the database constraints.
+    pub fn validate_company(company: &str) -> Result<(), String> {
+        if company.is_empty() {
+            Err("company must not be empty".to_owned())
+        } else if company.chars().count() > 256 {
+            Err("company must not be longer than 256 characters".to_owned())
+        } else if company == auth::ALL_COMPANIES {
+            Err(format!("company must not be {:?}", auth::ALL_COMPANIES))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Normalizes empty optional values to `None` and checks the vehicle against the database
+    /// constraints and the values the given value sets allow for its company.
+    ///
+    /// `stored` is the vehicle as currently stored for the same company, if any; values it already
+    /// has remain acceptable even if the value sets no longer allow them, so that unrelated changes
+    /// are possible.
+    ///
+    /// Returns a human-readable description of the first problem encountered.
+    pub fn validate(&mut self, company: &str, value_sets: &ValueSets, stored: Option<&ExportedVehicle>) -> Result<(), String> {
+        fn normalize_optional(value: &mut Option<String>) {
+            if value.as_ref().map(|v| v.is_empty()).unwrap_or(false) {
+                *value = None;
+            }
+        }
+        fn check_length(name: &str, value: &str, max_chars: usize) -> Result<(), String> {
+            if value.chars().count() > max_chars {
+                Err(format!("'{}' must not be longer than {} characters", name, max_chars))
+            } else {
+                Ok(())
+            }
+        }
+
+        normalize_optional(&mut self.in_service_since);
+        normalize_optional(&mut self.out_of_service_since);
+        normalize_optional(&mut self.manufacturer);
+        normalize_optional(&mut self.depot);
+
+        if self.number.is_empty() {
+            return Err("'number' must not be empty".to_owned());
+        }
+        if self.type_code.is_empty() {
+            return Err("'type_code' must not be empty".to_owned());
+        }
+        if self.vehicle_class.is_empty() {
+            return Err("'vehicle_class' must not be empty".to_owned());
+        }
+        check_length("number", &self.number, 256)?;
+        check_length("type_code", &self.type_code, 256)?;
+        check_length("vehicle_class", &self.vehicle_class, 32)?;
+        check_length("in_service_since", self.in_service_since.as_deref().unwrap_or(""), 32)?;
+        check_length("out_of_service_since", self.out_of_service_since.as_deref().unwrap_or(""), 32)?;
+        check_length("manufacturer", self.manufacturer.as_deref().unwrap_or(""), 32)?;
+        check_length("depot", self.depot.as_deref().unwrap_or(""), 256)?;
+        if !self.other_data.is_object() {
+            return Err("'other_data' is not a JSON object".to_owned());
+        }
+        for power_source in &self.power_sources {
+            if power_source.is_empty() {
+                return Err("empty value in 'power_sources'".to_owned());
+            }
+            check_length("power_sources", power_source, 256)?;
+        }
+
+        fn check_allowed(name: &str, value: &str, company: &str, value_set: &ValueSet, stored: bool) -> Result<(), String> {
+            if stored || value_set.allows(company, value) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} {:?} is not one of the values allowed for company {:?}; administrators can change this on the value sets page",
+                    name, value, company,
+                ))
+            }
+        }
+
+        let stored_veh_class = stored.is_some_and(|s| s.vehicle_class == self.vehicle_class);
+        check_allowed("vehicle class", &self.vehicle_class, company, &value_sets.veh_classes, stored_veh_class)?;
+        for power_source in &self.power_sources {
+            let stored_power_source = stored.is_some_and(|s| s.power_sources.contains(power_source));
+            check_allowed("power source", power_source, company, &value_sets.power_sources, stored_power_source)?;
+        }
+        if let Some(depot) = &self.depot {
+            if !value_sets.depots.is_empty() {
+                let stored_depot = stored.is_some_and(|s| s.depot.as_ref() == Some(depot));
+                check_allowed("depot", depot, company, &value_sets.depots, stored_depot)?;
+            }
+        }
+        if let Some(manufacturer) = &self.manufacturer {
+            if !value_sets.manufacturers.is_empty() {
+                let stored_manufacturer = stored.is_some_and(|s| s.manufacturer.as_ref() == Some(manufacturer));
+                check_allowed("manufacturer", manufacturer, company, &value_sets.manufacturers, stored_manufacturer)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A vehicle with its company, as output in the NDJSON export.
+#[derive(Clone, Debug, Serialize)]
+struct NdjsonVehicle<'a> {
+    pub company: &'a str,
+    #[serde(flatten)] pub vehicle: &'a ExportedVehicle,
+}
+
+/// The vehicles of one company or of all companies keyed by company.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+enum Export {
+    Company(Vec<ExportedVehicle>),
+    All(BTreeMap<String, Vec<ExportedVehicle>>),
+}
+
+/// A response body that is fed through a channel.
+///
+/// Sending an error aborts the response, so that the client notices that the body is incomplete.
+struct ChannelBody {
+    receiver: tokio::sync::mpsc::Receiver<Result<Bytes, String>>,
+}
+impl hyper::body::Body for ChannelBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.receiver.poll_recv(cx)
+            .map(|data_opt| data_opt.map(|data_res| data_res.map(Frame::data).map_err(BodyError::from)))
+    }
+}
+
+type BodyError = Box<dyn std::error::Error + Send + Sync>;
+type ResponseBody = BoxBody<Bytes, BodyError>;
+
+fn boxed_body(body: Full<Bytes>) -> ResponseBody {
+    body.map_err(|never| match never {}).boxed()
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
@@ -79,7 +262,14 @@ struct IndexTemplate {
     pub vehicles: Vec<BimPart>,
     pub base_path: String,
     pub page: i64,
-    pub company: String,
+    pub page_count: i64,
+    pub total: i64,
+    pub filter: VehicleFilter,
+    pub filter_query: String,
+    pub sort: VehicleSort,
+    pub sort_query: String,
+    pub allowed_power_sources: Vec<AllowedValue>,
+    pub may_add: bool,
 }
 
 #[derive(Template)]
@@ -90,15 +280,26 @@ struct AddEditTemplate {
     pub company: Option<String>,
     pub veh_number: Option<String>,
     pub type_code: Option<String>,
-    pub veh_class: Option<String>,
-    pub power_sources: BTreeSet<String>,
     pub in_service_since: Option<String>,
     pub out_of_service_since: Option<String>,
     pub manufacturer: Option<String>,
     pub depot: Option<String>,
     pub other_data: Option<String>,
-    pub allowed_veh_classes: BTreeSet<String>,
-    pub allowed_power_sources: BTreeSet<String>,
+    pub veh_class_options: Vec<ValueOption>,
+    pub power_source_options: Vec<ValueOption>,
+    pub depot_options: Vec<ValueOption>,
+    pub manufacturer_options: Vec<ValueOption>,
+}
+
+#[derive(Template)]
+#[template(path = "delete_confirm.html")]
+struct DeleteConfirmTemplate {
+    pub base_path: String,
+    pub id: i64,
+    pub company: String,
+    pub veh_number: String,
+    pub coupling_id: i64,
+    pub coupling_numbers: Vec<String>,
 }
 
 #[derive(Template)]
@@ -106,6 +307,7 @@ struct AddEditTemplate {
 struct CouplingListTemplate {
     pub base_path: String,
     pub couplings: Vec<CouplingPart>,
+    pub may_add: bool,
 }
 
 #[derive(Template)]
@@ -113,7 +315,7 @@ struct CouplingListTemplate {
 struct CouplingAddEditTemplate {
     pub base_path: String,
     pub edit_id: Option<i64>,
-    pub company_to_vehicles: BTreeMap<String, BTreeSet<String>>,
+    pub company_to_vehicles: BTreeMap<String, Vec<String>>,
     pub company: Option<String>,
     pub vehicles: Vec<String>,
 }
@@ -124,10 +326,29 @@ impl CouplingAddEditTemplate {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Template)]
+#[template(path = "import.html")]
+struct ImportTemplate {
+    pub base_path: String,
+}
+
+#[derive(Template)]
+#[template(path = "import_preview.html")]
+struct ImportPreviewTemplate {
+    pub base_path: String,
+    pub plan: ImportPlan,
+    pub insert_count: usize,
+    pub update_count: usize,
+    pub unchanged_count: usize,
+    pub vehicles_json: String,
+    pub plan_json: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, clap::ValueEnum)]
 enum ExportFormat {
     Json,
     Cbor,
+    Csv,
 }
 
 
@@ -137,7 +358,7 @@ fn path_to_parts<'a>(path: &'a str, strip_first_empty: bool) -> Option<Vec<Cow<'
     for piece in path.split('/') {
         let part = percent_encoding::percent_decode_str(piece)
             .decode_utf8().ok()?;
-        if !(strip_first_empty && first_round && part.len() == 0) {
+        if !(strip_first_empty && first_round && part.is_empty()) {
             ret.push(part);
         }
         first_round = false;
@@ -146,7 +367,7 @@ fn path_to_parts<'a>(path: &'a str, strip_first_empty: bool) -> Option<Vec<Cow<'
 }
 
 
-fn strip_path_prefix<'h, 'n, H: AsRef<str>, N: AsRef<str>>(haystack: &'h [H], needle: &'n [N]) -> Option<&'h [H]> {
+fn strip_path_prefix<'h, H: AsRef<str>, N: AsRef<str>>(haystack: &'h [H], needle: &[N]) -> Option<&'h [H]> {
     if needle.len() > haystack.len() {
         return None;
     }
@@ -174,6 +395,22 @@ fn return_400(reason: &str) -> Response<Full<Bytes>> {
         .body(Full::new(Bytes::from(body_string)))
         .unwrap_or_else(|_| return_500())
 }
+fn return_409(reason: &str) -> Response<Full<Bytes>> {
+    let body_string = format!("409 Conflict: {}", reason);
+    Response::builder()
+        .status(409)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from(body_string)))
+        .unwrap_or_else(|_| return_500())
+}
+fn return_403(reason: &str) -> Response<Full<Bytes>> {
+    let body_string = format!("403 Forbidden: {}", reason);
+    Response::builder()
+        .status(403)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from(body_string)))
+        .unwrap_or_else(|_| return_500())
+}
 fn return_404() -> Response<Full<Bytes>> {
     Response::builder()
         .status(400)
@@ -194,33 +431,174 @@ fn return_405(method: &Method, allowed_methods: &[Method]) -> Response<Full<Byte
 }
 
 
-async fn db_connect() -> Option<tokio_postgres::Client> {
-    let db_config = &CONFIG
-        .get().expect("CONFIG not set?!")
-        .db;
-    let connect_res = tokio_postgres::Config::new()
-        .host(&db_config.hostname)
+fn return_503(retry_after_s: u64) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(503)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .header("Retry-After", retry_after_s.to_string())
+        .body(Full::new(Bytes::from("503 Service Unavailable")))
+        .unwrap_or_else(|_| return_500())
+}
+
+
+/// Why no database connection could be obtained.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum DbConnectError {
+    /// All connections of the pool are in use and none became available in time.
+    PoolExhausted,
+    /// The connection to the database failed.
+    Failed,
+}
+impl DbConnectError {
+    /// The number of seconds after which clients are asked to retry if the pool is exhausted.
+    pub const RETRY_AFTER_S: u64 = 5;
+
+    pub fn response(&self) -> Response<Full<Bytes>> {
+        match self {
+            Self::PoolExhausted => return_503(Self::RETRY_AFTER_S),
+            Self::Failed => return_500(),
+        }
+    }
+}
+
+fn make_db_tls_connector(db_config: &DbConfig) -> Result<MakeTlsConnector, String> {
+    let mut builder = TlsConnector::builder();
+    match db_config.ssl_mode {
+        DbSslMode::Disable|DbSslMode::Prefer|DbSslMode::Require => {
+            // like libpq, verify the certificate chain (but not the hostname) if a root certificate is given
+            builder
+                .danger_accept_invalid_certs(db_config.ssl_root_cert.is_none())
+                .danger_accept_invalid_hostnames(true);
+        },
+        DbSslMode::VerifyFull => {},
+    }
+
+    if let Some(root_cert_path) = &db_config.ssl_root_cert {
+        let root_cert_bytes = std::fs::read(root_cert_path)
+            .map_err(|e| format!("failed to read {:?}: {}", root_cert_path, e))?;
+        let root_certs = Certificate::stack_from_pem(&root_cert_bytes)
+            .map_err(|e| format!("failed to parse certificates in {:?}: {}", root_cert_path, e))?;
+        for root_cert in root_certs {
+            builder.add_root_certificate(root_cert);
+        }
+        builder.disable_built_in_roots(true);
+    }
+
+    match (&db_config.ssl_client_cert, &db_config.ssl_client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert_bytes = std::fs::read(cert_path)
+                .map_err(|e| format!("failed to read {:?}: {}", cert_path, e))?;
+            let key_bytes = std::fs::read(key_path)
+                .map_err(|e| format!("failed to read {:?}: {}", key_path, e))?;
+            let identity = Identity::from_pkcs8(&cert_bytes, &key_bytes)
+                .map_err(|e| format!("failed to load client certificate {:?} with PKCS#8 key {:?}: {}", cert_path, key_path, e))?;
+            builder.identity(identity);
+        },
+        (None, None) => {},
+        _ => return Err("ssl_client_cert and ssl_client_key must be set together".to_owned()),
+    }
+
+    let connector = builder.build()
+        .map_err(|e| format!("failed to set up TLS: {}", e))?;
+    Ok(MakeTlsConnector::new(connector))
+}
+
+fn create_db_pool(db_config: &DbConfig) -> Result<Pool, String> {
+    let mut pg_config = tokio_postgres::Config::new();
+    pg_config
         .user(&db_config.username)
         .password(&db_config.password)
         .dbname(&db_config.db_name)
-        .port(db_config.port)
-        .connect(tokio_postgres::NoTls).await;
-    let (client, connection) = match connect_res {
-        Ok(cc) => cc,
+        .port(db_config.port);
+
+    // the server never offers TLS on Unix sockets
+    let ssl_mode = if let Some(socket_path) = &db_config.socket_path {
+        pg_config.host_path(socket_path);
+        if db_config.ssl_mode != DbSslMode::Disable {
+            warn!("ignoring ssl_mode {:?} for connection via Unix socket", db_config.ssl_mode);
+        }
+        DbSslMode::Disable
+    } else {
+        pg_config.host(&db_config.hostname);
+        db_config.ssl_mode
+    };
+    pg_config.ssl_mode(match ssl_mode {
+        DbSslMode::Disable => SslMode::Disable,
+        DbSslMode::Prefer => SslMode::Prefer,
+        DbSslMode::Require|DbSslMode::VerifyFull => SslMode::Require,
+    });
+
+    let recycling_method = if db_config.pool_health_check {
+        RecyclingMethod::Verified
+    } else {
+        RecyclingMethod::Fast
+    };
+    let manager_config = ManagerConfig { recycling_method };
+    let manager = if ssl_mode == DbSslMode::Disable {
+        Manager::from_config(pg_config, tokio_postgres::NoTls, manager_config)
+    } else {
+        let tls_connector = make_db_tls_connector(db_config)?;
+        Manager::from_config(pg_config, tls_connector, manager_config)
+    };
+    Pool::builder(manager)
+        .max_size(db_config.pool_max_size)
+        .wait_timeout(Some(Duration::from_millis(db_config.pool_wait_timeout_ms)))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|e| format!("failed to build database pool: {}", e))
+}
+
+/// Regularly closes connections which have not been used for the configured idle timeout.
+async fn prune_idle_db_connections() {
+    let idle_timeout_s = config::current()
+        .db.pool_idle_timeout_s;
+    if idle_timeout_s == 0 {
+        return;
+    }
+    let idle_timeout = Duration::from_secs(idle_timeout_s);
+    let mut interval = tokio::time::interval((idle_timeout / 2).max(Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        let pool = DB_POOL.get().expect("DB_POOL not set?!");
+        pool.retain(|_client, metrics| metrics.last_used() < idle_timeout);
+    }
+}
+
+/// Reloads the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_config_on_hangup() {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(h) => h,
         Err(e) => {
-            error!("failed to connect to database: {}", e);
-            return None;
+            error!("failed to listen for SIGHUP: {}", e);
+            return;
         },
     };
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            error!("Postgres connection error: {}", e);
+    while hangups.recv().await.is_some() {
+        info!("received SIGHUP; reloading configuration");
+        if let Err(e) = config::reload() {
+            error!("failed to reload configuration; keeping the previous one: {}", e);
         }
-    });
-    Some(client)
+    }
+}
+
+/// Borrows a connection from the pool. The connection returns to the pool when it is dropped.
+async fn db_connect() -> Result<Object, DbConnectError> {
+    let pool = DB_POOL.get().expect("DB_POOL not set?!");
+    match pool.get().await {
+        Ok(client) => Ok(client),
+        Err(PoolError::Timeout(TimeoutType::Wait)) => {
+            warn!("database pool exhausted ({} connections in use)", pool.status().size);
+            Err(DbConnectError::PoolExhausted)
+        },
+        Err(e) => {
+            error!("failed to connect to database: {}", e);
+            Err(DbConnectError::Failed)
+        },
+    }
 }
 
-fn cow_replace<'t, 'o, 'n>(text: Cow<'t, str>, old: &'o str, new: &'n str) -> Cow<'t, str> {
+fn cow_replace<'t>(text: Cow<'t, str>, old: &str, new: &str) -> Cow<'t, str> {
     if text.contains(old) {
         Cow::Owned(text.replace(old, new))
     } else {
@@ -271,17 +649,24 @@ async fn handle_index(_remote_addr: SocketAddr, request: Request<Incoming>) -> R
         Some(qp) => qp,
         None => return return_400("invalid UTF-8 in query"),
     };
+    let principal = auth::principal(&request);
+    let view_scope = auth::view_scope(principal.as_ref());
 
     let db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
     };
 
     // obtain companies
-    let company_rows_res = db_conn.query(
-        "SELECT DISTINCT company FROM bimdb.bims",
-        &[],
-    ).await;
+    let mut company_params = Vec::new();
+    let company_query = format!(
+        "SELECT DISTINCT company FROM bimdb.bims WHERE deleted_at IS NULL AND {}",
+        view_scope.sql_condition("company", &mut company_params),
+    );
+    let company_param_refs: Vec<&(dyn ToSql + Sync)> = company_params.iter()
+        .map(|c| c as &(dyn ToSql + Sync))
+        .collect();
+    let company_rows_res = db_conn.query(&company_query, &company_param_refs).await;
     let company_rows = match company_rows_res {
         Ok(cr) => cr,
         Err(e) => {
@@ -296,51 +681,72 @@ async fn handle_index(_remote_addr: SocketAddr, request: Request<Incoming>) -> R
     }
 
     // obtain vehicles
-    let per_page = CONFIG
-        .get().expect("CONFIG not set?!")
+    let per_page = config::current()
         .vehicles_per_page;
     let page_str = query_pairs.iter()
         .filter(|(k, _v)| k == "page")
-        .map(|(_k, v)| v.as_ref().map(|v2| v2.as_str()))
-        .flatten()
-        .last()
+        .filter_map(|(_k, v)| v.as_ref().map(|v2| v2.as_str()))
+        .next_back()
         .unwrap_or("0");
-    let company_str = query_pairs.iter()
-        .filter(|(k, _v)| k == "company")
-        .map(|(_k, v)| v.as_ref().map(|v2| v2.as_str().trim()))
-        .flatten()
-        .last()
-        .unwrap_or("");
+    let filter = match VehicleFilter::from_query(&query_pairs) {
+        Ok(f) => f,
+        Err(e) => return return_400(&e),
+    };
+    let sort = match VehicleSort::from_query(&query_pairs) {
+        Ok(s) => s,
+        Err(e) => return return_400(&e),
+    };
     let page: i64 = match page_str.parse() {
         Ok(pn) => if pn < 0 {
-            return return_400(&format!("'page' must be >= 0"));
+            return return_400("'page' must be >= 0");
         } else {
             pn
         },
         Err(_) => return return_400("invalid 'page'"),
     };
     let page_offset = page * per_page;
-    let mut query_params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(3);
+    let mut filter_values = Vec::new();
+    let condition = format!(
+        "{} AND {}",
+        filter.sql_condition(&mut filter_values),
+        view_scope.sql_condition("b.company", &mut filter_values),
+    );
+    let mut query_params: Vec<&(dyn ToSql + Sync)> = filter_values.iter()
+        .map(|v| v as &(dyn ToSql + Sync))
+        .collect();
+
+    let count_query = format!("SELECT COUNT(*) FROM bimdb.bims b WHERE b.deleted_at IS NULL AND {}", condition);
+    let total: i64 = match db_conn.query_one(&count_query, &query_params).await {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            error!("failed to count vehicles: {}", e);
+            return return_500();
+        },
+    };
+    let page_count = ((total + per_page - 1) / per_page).max(1);
+
     query_params.push(&per_page);
     query_params.push(&page_offset);
     let query = format!(
         "
             SELECT
-                id, company, veh_number, type_code,
-                veh_class, in_service_since, out_of_service_since, manufacturer,
-                depot
+                b.id, b.company, b.veh_number, b.type_code,
+                b.veh_class, b.in_service_since, b.out_of_service_since, b.manufacturer,
+                b.depot
             FROM
-                bimdb.bims
-            {}
+                bimdb.bims b
+            WHERE
+                b.deleted_at IS NULL
+                AND {}
             ORDER BY
-                company, veh_number, id
-            LIMIT $1 OFFSET $2
+                {}
+            LIMIT ${} OFFSET ${}
         ",
-        if company_str.len() > 0 { "WHERE company = $3" } else { "" },
+        condition,
+        sort.sql_order_by(),
+        query_params.len() - 1,
+        query_params.len(),
     );
-    if company_str.len() > 0 {
-        query_params.push(&company_str);
-    }
     let vehicle_rows = match db_conn.query(&query, &query_params).await {
         Ok(vr) => vr,
         Err(e) => {
@@ -360,6 +766,7 @@ async fn handle_index(_remote_addr: SocketAddr, request: Request<Incoming>) -> R
         let out_of_service_since: Option<String> = row.get(6);
         let manufacturer: Option<String> = row.get(7);
         let depot: Option<String> = row.get(8);
+        let editable = auth::may_edit(principal.as_ref(), &company);
         vehicles.push(BimPart {
             id,
             company,
@@ -370,16 +777,29 @@ async fn handle_index(_remote_addr: SocketAddr, request: Request<Incoming>) -> R
             out_of_service_since,
             manufacturer,
             depot,
+            editable,
         })
     }
 
-    let config = CONFIG.get().expect("CONFIG not set?!");
+    let value_sets = match value_sets::load_value_sets(&db_conn).await {
+        Some(vs) => vs,
+        None => return return_500(),
+    };
+
+    let config = config::current();
     let template = IndexTemplate {
         companies,
         vehicles,
         base_path: config.http.base_path.clone(),
         page,
-        company: company_str.to_owned(),
+        page_count,
+        total,
+        filter_query: filter.to_query_string(),
+        filter,
+        sort_query: sort.to_query_string(),
+        sort,
+        allowed_power_sources: value_sets.power_sources.values,
+        may_add: principal.map(|p| p.may_edit_any()).unwrap_or(false),
     };
     let template_text = template.render()
         .expect("failed to render template");
@@ -390,35 +810,14 @@ async fn handle_index(_remote_addr: SocketAddr, request: Request<Incoming>) -> R
         .unwrap_or_else(|_| return_500())
 }
 
-#[instrument(skip_all)]
-async fn handle_export(_remote_addr: SocketAddr, request: Request<Incoming>, format: ExportFormat) -> Response<Full<Bytes>> {
-    if request.method() != Method::GET {
-        return return_405(request.method(), &[Method::GET]);
-    }
-
-    let query_pairs = match get_query_pairs(request.uri().query()) {
-        Some(qp) => qp,
-        None => return return_400("invalid UTF-8 in query"),
-    };
-
-    let company_opt = query_pairs.iter()
-        .filter(|(k, _v)| k == "company")
-        .map(|(_k, v)| v)
-        .flatten()
-        .last();
-    let company = match company_opt {
-        Some(c) => c,
-        None => return return_400("required parameter 'company' missing"),
-    };
-
-    let db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
-    };
-
+/// Loads the vehicles matching the given SQL condition, which refers to the vehicle as `b` and to
+/// the parameter as `$1`.
+///
+/// Returns the ID, company and data of each vehicle, ordered by number. Deleted vehicles are skipped.
+async fn load_vehicles_where<C: GenericClient>(db_conn: &C, condition: &str, param: &(dyn ToSql + Sync)) -> Option<Vec<(i64, String, ExportedVehicle)>> {
     // obtain fixed couplings
     let mut bim_id_to_coupling: BTreeMap<i64, Vec<String>> = BTreeMap::new();
-    let coupling_rows_res = db_conn.query(
+    let coupling_query = format!(
         "
             SELECT
                 b.id, coupled.veh_number
@@ -431,17 +830,18 @@ async fn handle_export(_remote_addr: SocketAddr, request: Request<Incoming>, for
                 INNER JOIN bimdb.bims coupled
                     ON coupled.id = cpl2bim.bim_id
             WHERE
-                b.company = $1
+                b.deleted_at IS NULL
+                AND {}
             ORDER BY
                 b.id, cpl2bim.position
         ",
-        &[&company],
-    ).await;
-    let coupling_rows = match coupling_rows_res {
+        condition,
+    );
+    let coupling_rows = match db_conn.query(&coupling_query, &[param]).await {
         Ok(cr) => cr,
         Err(e) => {
-            error!("failed to obtain coupling rows for company {:?}: {}", e, company);
-            return return_500();
+            error!("failed to obtain coupling rows for {:?}: {}", condition, e);
+            return None;
         },
     };
     for coupling_row in coupling_rows {
@@ -449,13 +849,13 @@ async fn handle_export(_remote_addr: SocketAddr, request: Request<Incoming>, for
         let coupled_number: String = coupling_row.get(1);
         bim_id_to_coupling
             .entry(bim_id)
-            .or_insert_with(|| Vec::new())
+            .or_default()
             .push(coupled_number);
     }
 
     // obtain power sources
     let mut bim_id_to_power_sources: BTreeMap<i64, BTreeSet<String>> = BTreeMap::new();
-    let power_source_rows_res = db_conn.query(
+    let power_source_query = format!(
         "
             SELECT
                 b.id, ps.power_source
@@ -464,17 +864,18 @@ async fn handle_export(_remote_addr: SocketAddr, request: Request<Incoming>, for
                 INNER JOIN bimdb.power_sources ps
                     ON ps.bim_id = b.id
             WHERE
-                b.company = $1
+                b.deleted_at IS NULL
+                AND {}
             ORDER BY
                 b.id, ps.power_source
         ",
-        &[&company],
-    ).await;
-    let power_source_rows = match power_source_rows_res {
+        condition,
+    );
+    let power_source_rows = match db_conn.query(&power_source_query, &[param]).await {
         Ok(psr) => psr,
         Err(e) => {
-            error!("failed to obtain power source rows for company {:?}: {}", e, company);
-            return return_500();
+            error!("failed to obtain power source rows for {:?}: {}", condition, e);
+            return None;
         },
     };
     for power_source_row in power_source_rows {
@@ -482,146 +883,989 @@ async fn handle_export(_remote_addr: SocketAddr, request: Request<Incoming>, for
         let power_source: String = power_source_row.get(1);
         bim_id_to_power_sources
             .entry(bim_id)
-            .or_insert_with(|| BTreeSet::new())
+            .or_default()
             .insert(power_source);
     }
 
     // obtain vehicles
-    let vehicle_rows_res = db_conn.query(
+    let number_order = natural_sort::sql_order_keys("b.veh_number");
+    let vehicle_query = format!(
         "
-        SELECT
-            b.id, b.veh_number, b.type_code, b.veh_class,
-            b.in_service_since, b.out_of_service_since, b.manufacturer, b.depot,
-            b.other_data
-        FROM
-            bimdb.bims b
-        WHERE
-            b.company = $1
-        ORDER BY
-            b.veh_number, b.id
+            SELECT
+                b.id, b.company, b.veh_number, b.type_code, b.veh_class,
+                b.in_service_since, b.out_of_service_since, b.manufacturer, b.depot,
+                b.other_data
+            FROM
+                bimdb.bims b
+            WHERE
+                b.deleted_at IS NULL
+                AND {}
+            ORDER BY
+                {}, {}, b.id
         ",
-        &[&company],
-    ).await;
-    let vehicle_rows = match vehicle_rows_res {
+        condition,
+        number_order[0], number_order[1],
+    );
+    let vehicle_rows = match db_conn.query(&vehicle_query, &[param]).await {
         Ok(vr) => vr,
         Err(e) => {
-            error!("failed to obtain vehicle rows: {}", e);
-            return return_500();
+            error!("failed to obtain vehicle rows for {:?}: {}", condition, e);
+            return None;
+        },
+    };
+
+    let mut vehicles = Vec::with_capacity(vehicle_rows.len());
+    for row in vehicle_rows {
+        let bim_id: i64 = row.get(0);
+        let company: String = row.get(1);
+        let number: String = row.get(2);
+        let type_code: String = row.get(3);
+        let vehicle_class: String = row.get(4);
+        let in_service_since: Option<String> = row.get(5);
+        let out_of_service_since: Option<String> = row.get(6);
+        let manufacturer: Option<String> = row.get(7);
+        let depot: Option<String> = row.get(8);
+        let other_data: serde_json::Value = row.get(9);
+
+        let fixed_coupling = bim_id_to_coupling.remove(&bim_id)
+            .unwrap_or_else(|| Vec::with_capacity(0));
+        let power_sources = bim_id_to_power_sources.remove(&bim_id)
+            .unwrap_or_default();
+
+        vehicles.push((bim_id, company, ExportedVehicle {
+            number,
+            vehicle_class,
+            type_code,
+            in_service_since,
+            out_of_service_since,
+            manufacturer,
+            depot,
+            other_data,
+            fixed_coupling,
+            power_sources,
+        }));
+    }
+    Some(vehicles)
+}
+
+/// Loads the vehicle with the given ID as currently stored if it belongs to the given company, e.g.
+/// to pass it to [`ExportedVehicle::validate`].
+async fn load_stored_vehicle<C: GenericClient>(db_conn: &C, id: i64, company: &str) -> Option<Option<ExportedVehicle>> {
+    let mut vehicles = load_vehicles_where(db_conn, "b.id = $1", &id).await?;
+    let stored = vehicles.pop()
+        .filter(|(_id, stored_company, _vehicle)| stored_company == company)
+        .map(|(_id, _company, vehicle)| vehicle);
+    Some(stored)
+}
+
+async fn load_company_vehicles<C: GenericClient>(db_conn: &C, company: &str) -> Option<Vec<ExportedVehicle>> {
+    let vehicles = load_vehicles_where(db_conn, "b.company = $1", &company).await?;
+    Some(vehicles.into_iter().map(|(_id, _company, vehicle)| vehicle).collect())
+}
+
+/// Loads the vehicles of all companies within the given scope, keyed by company.
+async fn load_all_vehicles<C: GenericClient>(db_conn: &C, scope: &ViewScope) -> Option<BTreeMap<String, Vec<ExportedVehicle>>> {
+    let companies = load_companies(db_conn, scope).await?;
+    let mut company_to_vehicles = BTreeMap::new();
+    for company in companies {
+        let vehicles = load_company_vehicles(db_conn, &company).await?;
+        company_to_vehicles.insert(company, vehicles);
+    }
+    Some(company_to_vehicles)
+}
+
+/// Loads the names of the companies within the given scope which have vehicles.
+async fn load_companies<C: GenericClient>(db_conn: &C, scope: &ViewScope) -> Option<Vec<String>> {
+    let mut params = Vec::new();
+    let query = format!(
+        "SELECT DISTINCT company FROM bimdb.bims WHERE deleted_at IS NULL AND {} ORDER BY company",
+        scope.sql_condition("company", &mut params),
+    );
+    let param_refs: Vec<&(dyn ToSql + Sync)> = params.iter()
+        .map(|p| p as &(dyn ToSql + Sync))
+        .collect();
+    let company_rows_res = db_conn.query(&query, &param_refs).await;
+    let company_rows = match company_rows_res {
+        Ok(cr) => cr,
+        Err(e) => {
+            error!("failed to obtain companies: {}", e);
+            return None;
         },
     };
+    let companies = company_rows.iter()
+        .map(|row| row.get(0))
+        .collect();
+    Some(companies)
+}
+
+/// Begins a read-only transaction in which all queries see the same snapshot of the database, so that
+/// exports spanning multiple queries are consistent.
+async fn begin_snapshot(db_conn: &mut Object) -> Result<deadpool_postgres::Transaction<'_>, tokio_postgres::Error> {
+    db_conn.build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start().await
+}
 
-    let (data, content_type) = match format {
+/// Serializes the export in the given format and returns the data along with its content type.
+fn encode_export(export: &Export, format: ExportFormat) -> Result<(Vec<u8>, &'static str), String> {
+    match format {
         ExportFormat::Json => {
-            let mut vehicles = Vec::new();
-            let empty_coupling = Vec::with_capacity(0);
-            let no_power_sources = BTreeSet::new();
-            for row in vehicle_rows {
-                let bim_id: i64 = row.get(0);
-                let veh_number: String = row.get(1);
-                let type_code: String = row.get(2);
-                let veh_class: String = row.get(3);
-                let in_service_since: Option<String> = row.get(4);
-                let out_of_service_since: Option<String> = row.get(5);
-                let manufacturer: Option<String> = row.get(6);
-                let depot: Option<String> = row.get(7);
-                let other_data: serde_json::Value = row.get(8);
-
-                let fixed_coupling = bim_id_to_coupling.get(&bim_id)
-                    .unwrap_or(&empty_coupling);
-                let power_sources = bim_id_to_power_sources.get(&bim_id)
-                    .unwrap_or(&no_power_sources);
-
-                vehicles.push(serde_json::json!({
-                    "number": veh_number,
-                    "vehicle_class": veh_class,
-                    "type_code": type_code,
-                    "in_service_since": in_service_since,
-                    "out_of_service_since": out_of_service_since,
-                    "manufacturer": manufacturer,
-                    "depot": depot,
-                    "other_data": other_data,
-                    "fixed_coupling": fixed_coupling,
-                    "power_sources": power_sources,
-                }));
-            }
-            let json_data = match serde_json::to_string_pretty(&vehicles) {
-                Ok(jt) => jt.into_bytes(),
-                Err(e) => {
-                    error!("failed to serialize vehicles to JSON: {}", e);
-                    return return_500();
-                },
-            };
-            (json_data, "application/json")
+            let json_text = serde_json::to_string_pretty(export)
+                .map_err(|e| format!("failed to serialize vehicles to JSON: {}", e))?;
+            Ok((json_text.into_bytes(), "application/json"))
         },
         ExportFormat::Cbor => {
-            let mut vehicles = Vec::new();
-            let empty_coupling = Vec::with_capacity(0);
-            let no_power_sources = BTreeSet::new();
-            for row in vehicle_rows {
-                let bim_id: i64 = row.get(0);
-                let veh_number: String = row.get(1);
-                let type_code: String = row.get(2);
-                let veh_class: String = row.get(3);
-                let in_service_since: Option<String> = row.get(4);
-                let out_of_service_since: Option<String> = row.get(5);
-                let manufacturer: Option<String> = row.get(6);
-                let depot: Option<String> = row.get(7);
-                let other_data: serde_json::Value = row.get(8);
-
-                let fixed_coupling = bim_id_to_coupling.get(&bim_id)
-                    .unwrap_or(&empty_coupling);
-                let power_sources = bim_id_to_power_sources.get(&bim_id)
-                    .unwrap_or(&no_power_sources);
-
-                let cbor_value_res = cbor!({
-                    "number" => veh_number,
-                    "vehicle_class" => veh_class,
-                    "type_code" => type_code,
-                    "in_service_since" => in_service_since,
-                    "out_of_service_since" => out_of_service_since,
-                    "manufacturer" => manufacturer,
-                    "depot" => depot,
-                    "other_data" => other_data,
-                    "fixed_coupling" => fixed_coupling,
-                    "power_sources" => power_sources,
-                });
-                let cbor_value = match cbor_value_res {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!("failed to construct CBOR value: {}", e);
-                        return return_500();
-                    },
-                };
-                vehicles.push(cbor_value);
-            }
             let mut cbor_data = Vec::new();
-            if let Err(e) = ciborium::into_writer(&vehicles, &mut cbor_data) {
-                error!("failed to serialize vehicles to CBOR: {}", e);
-                return return_500();
-            }
-            (cbor_data, "application/cbor")
+            ciborium::into_writer(export, &mut cbor_data)
+                .map_err(|e| format!("failed to serialize vehicles to CBOR: {}", e))?;
+            Ok((cbor_data, "application/cbor"))
+        },
+        ExportFormat::Csv => {
+            let vehicles = match export {
+                Export::Company(v) => v,
+                Export::All(_) => return Err("CSV can only export the vehicles of one company".to_owned()),
+            };
+            let csv_data = csv_vehicles::vehicles_to_csv(vehicles)
+                .map_err(|e| format!("failed to serialize vehicles to CSV: {}", e))?;
+            Ok((csv_data, "text/csv; charset=utf-8"))
         },
+    }
+}
+
+/// Parses a list of vehicles to import from data in the given format.
+fn decode_vehicles(data: &[u8], format: ExportFormat) -> Result<Vec<ExportedVehicle>, String> {
+    match format {
+        ExportFormat::Json => serde_json::from_slice(data)
+            .map_err(|e| format!("failed to parse JSON: {}", e)),
+        ExportFormat::Cbor => ciborium::from_reader(data)
+            .map_err(|e| format!("failed to parse CBOR: {}", e)),
+        ExportFormat::Csv => csv_vehicles::vehicles_from_csv(data),
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle_export(_remote_addr: SocketAddr, request: Request<Incoming>, format: ExportFormat) -> Response<Full<Bytes>> {
+    if request.method() != Method::GET {
+        return return_405(request.method(), &[Method::GET]);
+    }
+
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return return_400("invalid UTF-8 in query"),
+    };
+
+    // without a company, JSON and CBOR export the whole database keyed by company
+    let company_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == "company")
+        .flat_map(|(_k, v)| v)
+        .last();
+    if company_opt.is_none() && format == ExportFormat::Csv {
+        return return_400("required parameter 'company' missing");
+    }
+    let view_scope = auth::view_scope(auth::principal(&request).as_ref());
+    if let Some(company) = company_opt {
+        if !view_scope.includes(company) {
+            return return_403(&format!("you may not view vehicles of company {:?}", company));
+        }
+    }
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+    let db_txn = match begin_snapshot(&mut db_conn).await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin export transaction: {}", e);
+            return return_500();
+        },
+    };
+
+    let export = match company_opt {
+        Some(company) => match load_company_vehicles(&db_txn, company).await {
+            Some(v) => Export::Company(v),
+            None => return return_500(),
+        },
+        None => match load_all_vehicles(&db_txn, &view_scope).await {
+            Some(ctv) => Export::All(ctv),
+            None => return return_500(),
+        },
+    };
+
+    let (data, content_type) = match encode_export(&export, format) {
+        Ok(dct) => dct,
+        Err(e) => {
+            error!("{}", e);
+            return return_500();
+        },
+    };
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", content_type)
+        .body(Full::new(Bytes::from(data)))
+        .unwrap_or_else(|_| return_500())
+}
+
+#[instrument(skip_all)]
+async fn handle_ndjson_export(_remote_addr: SocketAddr, request: Request<Incoming>) -> Response<ResponseBody> {
+    if request.method() != Method::GET {
+        return return_405(request.method(), &[Method::GET]).map(boxed_body);
+    }
+
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return return_400("invalid UTF-8 in query").map(boxed_body),
+    };
+    let company_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == "company")
+        .filter_map(|(_k, v)| v.clone())
+        .next_back();
+    let view_scope = auth::view_scope(auth::principal(&request).as_ref());
+    if let Some(company) = &company_opt {
+        if !view_scope.includes(company) {
+            return return_403(&format!("you may not view vehicles of company {:?}", company)).map(boxed_body);
+        }
+    }
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response().map(boxed_body),
+    };
+
+    // stream one vehicle per line from a single snapshot, loading one company at a time; the task
+    // reports whether it managed to start so that early failures still get a proper status code
+    let (started_sender, started_receiver) = tokio::sync::oneshot::channel();
+    let (sender, receiver) = tokio::sync::mpsc::channel(64);
+    tokio::spawn(async move {
+        let db_txn = match begin_snapshot(&mut db_conn).await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("failed to begin export transaction: {}", e);
+                let _ = started_sender.send(false);
+                return;
+            },
+        };
+        let companies = match company_opt {
+            Some(c) => vec![c],
+            None => match load_companies(&db_txn, &view_scope).await {
+                Some(c) => c,
+                None => {
+                    let _ = started_sender.send(false);
+                    return;
+                },
+            },
+        };
+        if started_sender.send(true).is_err() {
+            return;
+        }
+
+        for company in companies {
+            let vehicles = match load_company_vehicles(&db_txn, &company).await {
+                Some(v) => v,
+                None => {
+                    let _ = sender.send(Err("failed to load vehicles".to_owned())).await;
+                    return;
+                },
+            };
+            for vehicle in &vehicles {
+                let line_value = NdjsonVehicle {
+                    company: &company,
+                    vehicle,
+                };
+                let mut line = match serde_json::to_vec(&line_value) {
+                    Ok(l) => l,
+                    Err(e) => {
+                        error!("failed to serialize vehicle to JSON: {}", e);
+                        let _ = sender.send(Err("failed to serialize vehicle".to_owned())).await;
+                        return;
+                    },
+                };
+                line.push(b'\n');
+                if sender.send(Ok(Bytes::from(line))).await.is_err() {
+                    // client went away
+                    return;
+                }
+            }
+        }
+    });
+    if started_receiver.await != Ok(true) {
+        return return_500().map(boxed_body);
+    }
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "application/x-ndjson")
+        .body(ChannelBody { receiver }.boxed())
+        .unwrap_or_else(|_| return_500().map(boxed_body))
+}
+
+fn render_import_preview(plan: ImportPlan, vehicles: &[ExportedVehicle]) -> Response<Full<Bytes>> {
+    let vehicles_json = match serde_json::to_string(vehicles) {
+        Ok(vj) => vj,
+        Err(e) => {
+            error!("failed to serialize vehicles to JSON: {}", e);
+            return return_500();
+        },
+    };
+    let plan_json = match serde_json::to_string(&plan) {
+        Ok(pj) => pj,
+        Err(e) => {
+            error!("failed to serialize import plan to JSON: {}", e);
+            return return_500();
+        },
+    };
+
+    let config = config::current();
+    let template = ImportPreviewTemplate {
+        base_path: config.http.base_path.clone(),
+        insert_count: plan.count(VehicleAction::Insert),
+        update_count: plan.count(VehicleAction::Update),
+        unchanged_count: plan.count(VehicleAction::Unchanged),
+        plan,
+        vehicles_json,
+        plan_json,
+    };
+    let template_text = template.render()
+        .expect("failed to render template");
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Full::new(Bytes::from(template_text)))
+        .unwrap_or_else(|_| return_500())
+}
+
+#[instrument(skip_all)]
+async fn handle_import(remote_addr: SocketAddr, request: Request<Incoming>, format: ExportFormat) -> Response<Full<Bytes>> {
+    if request.method() == Method::GET && format == ExportFormat::Json {
+        let config = config::current();
+        let template = ImportTemplate {
+            base_path: config.http.base_path.clone(),
+        };
+        let template_text = template.render()
+            .expect("failed to render template");
+        return Response::builder()
+            .status(200)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(template_text)))
+            .unwrap_or_else(|_| return_500());
+    }
+    if request.method() != Method::POST {
+        let allowed_methods: &[Method] = if format == ExportFormat::Json {
+            &[Method::GET, Method::POST]
+        } else {
+            &[Method::POST]
+        };
+        return return_405(request.method(), allowed_methods);
+    }
+
+    let query_pairs = match get_query_pairs(request.uri().query()) {
+        Some(qp) => qp,
+        None => return return_400("invalid UTF-8 in query"),
+    };
+
+    let company_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == "company")
+        .filter_map(|(_k, v)| v.as_ref().map(|v2| v2.trim()))
+        .next_back();
+    let company = match company_opt {
+        Some(c) => if c.is_empty() {
+            return return_400("parameter 'company' must not be empty");
+        } else {
+            c.to_owned()
+        },
+        None => return return_400("required parameter 'company' missing"),
+    };
+    let principal = auth::principal(&request);
+    let may_import = principal.as_ref()
+        .map(|p| p.may_edit(&company))
+        .unwrap_or(false);
+    if !may_import {
+        return return_403(&format!("you may not import vehicles of company {:?}", company));
+    }
+
+    // "dry-run" and "dry-run=html" render a preview page, "dry-run=json" returns the plan
+    let dry_run_opt = query_pairs.iter()
+        .filter(|(k, _v)| k == "dry-run")
+        .map(|(_k, v)| v.as_deref().unwrap_or(""))
+        .next_back();
+    let dry_run_json = match dry_run_opt {
+        None => None,
+        Some("") | Some("html") => Some(false),
+        Some("json") => Some(true),
+        Some(_) => return return_400("invalid parameter value for 'dry-run'"),
+    };
+
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return return_500();
+        },
+    };
+
+    let mut vehicles = match decode_vehicles(&request_bytes, format) {
+        Ok(v) => v,
+        Err(e) => return return_400(&e),
+    };
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return return_500();
+        },
+    };
+    if dry_run_json.is_none() {
+        if let Err(e) = set_change_context(&db_txn, principal.as_ref(), Some(remote_addr)).await {
+            error!("failed to set change context: {}", e);
+            return return_500();
+        }
+        if let Err(e) = import::lock_for_import(&db_txn).await {
+            error!("failed to lock tables for import: {}", e);
+            return return_500();
+        }
+    }
+
+    // validate against the locked state, as stored values are exempt from the value sets
+    let value_sets = match value_sets::load_value_sets(&db_txn).await {
+        Some(vs) => vs,
+        None => return return_500(),
+    };
+    let stored_vehicles = match load_company_vehicles(&db_txn, &company).await {
+        Some(sv) => sv,
+        None => return return_500(),
+    };
+    if let Err(e) = import::validate_import(&company, &mut vehicles, &value_sets, &stored_vehicles) {
+        return return_400(&e);
+    }
+
+    if let Some(as_json) = dry_run_json {
+        // the transaction is rolled back when it is dropped
+        let plan = match import::plan_import(&db_txn, &company, &vehicles).await {
+            Some(p) => p,
+            None => return return_500(),
+        };
+        if !as_json {
+            return render_import_preview(plan, &vehicles);
+        }
+
+        let plan_json = match serde_json::to_string_pretty(&plan) {
+            Ok(pj) => pj,
+            Err(e) => {
+                error!("failed to serialize import plan: {}", e);
+                return return_500();
+            },
+        };
+        return Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(plan_json)))
+            .unwrap_or_else(|_| return_500());
+    }
+
+    let plan = match import::plan_import(&db_txn, &company, &vehicles).await {
+        Some(p) => p,
+        None => return return_500(),
+    };
+    let summary = match import::apply_import(&db_txn, &vehicles, &plan).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("failed to import vehicles for company {:?}: {}", company, e);
+            return return_500();
+        },
+    };
+
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit import transaction: {}", e);
+        return return_500();
+    }
+
+    let summary_json = match serde_json::to_string_pretty(&summary) {
+        Ok(sj) => sj,
+        Err(e) => {
+            error!("failed to serialize import summary: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(summary_json)))
+        .unwrap_or_else(|_| return_500())
+}
+
+#[instrument(skip_all)]
+async fn handle_import_preview_confirm(remote_addr: SocketAddr, request: Request<Incoming>, confirm: bool) -> Response<Full<Bytes>> {
+    if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::POST]);
+    }
+
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return return_500();
+        },
+    };
+
+    let form_values: HashMap<Cow<str>, Cow<str>> = form_urlencoded::parse(&request_bytes)
+        .collect();
+
+    let company = match form_values.get("company") {
+        Some(c) => if c.trim().is_empty() {
+            return return_400("field 'company' must not be empty");
+        } else {
+            c.trim()
+        },
+        None => return return_400("field 'company' is required"),
+    };
+    if !principal.may_edit(company) {
+        return return_403(&format!("you may not import vehicles of company {:?}", company));
+    }
+    let data = match form_values.get("data") {
+        Some(d) => d,
+        None => return return_400("field 'data' is required"),
+    };
+    // the confirmation form always contains the normalized data as JSON
+    let format = form_values.get("format")
+        .map(|f| f.as_ref())
+        .unwrap_or("json");
+    let vehicles_res = match format {
+        "json" => serde_json::from_str(data)
+            .map_err(|e| format!("failed to parse field 'data' as JSON: {}", e)),
+        "csv" if !confirm => csv_vehicles::vehicles_from_csv(data.as_bytes()),
+        _ => return return_400("invalid value for field 'format'"),
+    };
+    let mut vehicles: Vec<ExportedVehicle> = match vehicles_res {
+        Ok(v) => v,
+        Err(e) => return return_400(&e),
+    };
+
+    let previewed_plan_opt: Option<ImportPlan> = if confirm {
+        match form_values.get("plan") {
+            Some(p) => match serde_json::from_str(p) {
+                Ok(pp) => Some(pp),
+                Err(e) => return return_400(&format!("failed to parse field 'plan' as JSON: {}", e)),
+            },
+            None => return return_400("field 'plan' is required"),
+        }
+    } else {
+        None
+    };
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return return_500();
+        },
+    };
+    if confirm {
+        if let Err(e) = set_change_context(&db_txn, Some(&principal), Some(remote_addr)).await {
+            error!("failed to set change context: {}", e);
+            return return_500();
+        }
+        if let Err(e) = import::lock_for_import(&db_txn).await {
+            error!("failed to lock tables for import: {}", e);
+            return return_500();
+        }
+    }
+
+    // validate against the locked state, as stored values are exempt from the value sets
+    let value_sets = match value_sets::load_value_sets(&db_txn).await {
+        Some(vs) => vs,
+        None => return return_500(),
+    };
+    let stored_vehicles = match load_company_vehicles(&db_txn, company).await {
+        Some(sv) => sv,
+        None => return return_500(),
+    };
+    if let Err(e) = import::validate_import(company, &mut vehicles, &value_sets, &stored_vehicles) {
+        return return_400(&e);
+    }
+
+    let plan = match import::plan_import(&db_txn, company, &vehicles).await {
+        Some(p) => p,
+        None => return return_500(),
+    };
+    let Some(previewed_plan) = previewed_plan_opt else {
+        // only previewing; the transaction is rolled back when it is dropped
+        return render_import_preview(plan, &vehicles);
+    };
+    if plan != previewed_plan {
+        return return_409("the database has changed since the preview was created; please preview the import again");
+    }
+    if let Err(e) = import::apply_import(&db_txn, &vehicles, &plan).await {
+        error!("failed to import vehicles for company {:?}: {}", company, e);
+        return return_500();
+    }
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit import transaction: {}", e);
+        return return_500();
+    }
+
+    let base_path = &config::current()
+        .http.base_path;
+    let redirect_path = format!("{}/?company={}", base_path, percent_encoding::utf8_percent_encode(company, percent_encoding::NON_ALPHANUMERIC));
+    Response::builder()
+        .status(302)
+        .header("Location", &redirect_path)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("redirecting...")))
+        .unwrap_or_else(|_| return_500())
+}
+
+/// Describes who makes the changes in the given transaction, which the history triggers record.
+///
+/// `remote_addr` is `None` for changes made from the command line.
+async fn set_change_context(db_txn: &Transaction<'_>, principal: Option<&Principal>, remote_addr: Option<SocketAddr>) -> Result<(), tokio_postgres::Error> {
+    let user_id = principal.map(|p| p.user_id.to_string()).unwrap_or_default();
+    let username = principal.map(|p| p.username.clone()).unwrap_or_default();
+    let token_id = principal.and_then(|p| p.token_id).map(|ti| ti.to_string()).unwrap_or_default();
+    db_txn.execute(
+        "
+            SELECT
+                set_config('bimdb.user_id', $1, TRUE),
+                set_config('bimdb.username', $2, TRUE),
+                set_config('bimdb.token_id', $3, TRUE),
+                set_config('bimdb.remote_addr', $4, TRUE)
+        ",
+        &[&user_id, &username, &token_id, &remote_addr.map(|ra| ra.ip().to_string()).unwrap_or_default()],
+    ).await?;
+    Ok(())
+}
+
+/// Locks the vehicle with the given ID for the rest of the transaction and returns its company, or
+/// `None` if it does not exist or has been deleted.
+async fn lock_vehicle_company(db_txn: &Transaction<'_>, id: i64) -> Result<Option<String>, tokio_postgres::Error> {
+    let row_opt = db_txn.query_opt(
+        "SELECT company FROM bimdb.bims WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        &[&id],
+    ).await?;
+    Ok(row_opt.map(|row| row.get(0)))
+}
+
+/// Locks the coupling with the given ID for the rest of the transaction and returns the company of
+/// its vehicles, or `None` if it does not exist. The inner value is `None` if the coupling has no
+/// vehicles.
+async fn lock_coupling_company(db_txn: &Transaction<'_>, id: i64) -> Result<Option<Option<String>>, tokio_postgres::Error> {
+    let row_opt = db_txn.query_opt(
+        "
+            SELECT
+                b.company
+            FROM
+                bimdb.couplings c
+                LEFT OUTER JOIN bimdb.coupling_bims cb ON cb.coupling_id = c.id
+                LEFT OUTER JOIN bimdb.bims b ON b.id = cb.bim_id
+            WHERE
+                c.id = $1
+            ORDER BY
+                cb.position
+            LIMIT 1
+            FOR UPDATE OF c
+        ",
+        &[&id],
+    ).await?;
+    Ok(row_opt.map(|row| row.get(0)))
+}
+
+#[derive(Debug)]
+enum TrashVehicleError {
+    Database(tokio_postgres::Error),
+    /// The vehicle is part of the coupling with this ID.
+    Coupled(i64),
+}
+impl From<tokio_postgres::Error> for TrashVehicleError {
+    fn from(e: tokio_postgres::Error) -> Self { Self::Database(e) }
+}
+
+/// Moves a vehicle, which must have been locked using [`lock_vehicle_company`], into the trash.
+///
+/// Vehicles in the trash are hidden everywhere except in the trash view, from which they can be
+/// restored or purged. Coupled vehicles must be removed from their coupling using
+/// [`uncouple_vehicle`] first.
+async fn trash_vehicle(db_txn: &Transaction<'_>, id: i64, principal: Option<&Principal>) -> Result<(), TrashVehicleError> {
+    let coupling_row_opt = db_txn.query_opt(
+        "SELECT coupling_id FROM bimdb.coupling_bims WHERE bim_id = $1",
+        &[&id],
+    ).await?;
+    if let Some(coupling_row) = coupling_row_opt {
+        return Err(TrashVehicleError::Coupled(coupling_row.get(0)));
+    }
+
+    let username = principal.map(|p| p.username.as_str());
+    db_txn.execute(
+        "UPDATE bimdb.bims SET deleted_at = CURRENT_TIMESTAMP, deleted_by = $1 WHERE id = $2 AND deleted_at IS NULL",
+        &[&username, &id],
+    ).await?;
+    Ok(())
+}
+
+/// Removes a vehicle, which must have been locked using [`lock_vehicle_company`], from its
+/// coupling. The remaining vehicles move up to close the gap; a coupling left with a single vehicle
+/// is dissolved.
+///
+/// Returns the ID of the coupling from which the vehicle was removed, or `None` if the vehicle was
+/// not coupled.
+async fn uncouple_vehicle(db_txn: &Transaction<'_>, id: i64) -> Result<Option<i64>, tokio_postgres::Error> {
+    let coupling_row_opt = db_txn.query_opt(
+        "SELECT coupling_id, position FROM bimdb.coupling_bims WHERE bim_id = $1",
+        &[&id],
+    ).await?;
+    let Some(coupling_row) = coupling_row_opt else {
+        return Ok(None);
+    };
+    let coupling_id: i64 = coupling_row.get(0);
+    let removed_position: i64 = coupling_row.get(1);
+    db_txn.execute(
+        "SELECT id FROM bimdb.couplings WHERE id = $1 FOR UPDATE",
+        &[&coupling_id],
+    ).await?;
+
+    db_txn.execute(
+        "DELETE FROM bimdb.coupling_bims WHERE bim_id = $1",
+        &[&id],
+    ).await?;
+    let remaining_rows = db_txn.query(
+        "SELECT bim_id, position FROM bimdb.coupling_bims WHERE coupling_id = $1 ORDER BY position",
+        &[&coupling_id],
+    ).await?;
+
+    if remaining_rows.len() < 2 {
+        db_txn.execute(
+            "DELETE FROM bimdb.coupling_bims WHERE coupling_id = $1",
+            &[&coupling_id],
+        ).await?;
+        db_txn.execute(
+            "DELETE FROM bimdb.couplings WHERE id = $1",
+            &[&coupling_id],
+        ).await?;
+        return Ok(Some(coupling_id));
+    }
+
+    // one at a time in ascending order so that the unique position constraint is never violated
+    for remaining_row in remaining_rows {
+        let bim_id: i64 = remaining_row.get(0);
+        let position: i64 = remaining_row.get(1);
+        if position > removed_position {
+            db_txn.execute(
+                "UPDATE bimdb.coupling_bims SET position = $1 WHERE bim_id = $2",
+                &[&(position - 1), &bim_id],
+            ).await?;
+        }
+    }
+    Ok(Some(coupling_id))
+}
+
+/// Inserts (if `id` is `None`) or updates a vehicle and replaces its power sources.
+///
+/// Returns the ID of the vehicle, or `None` if the vehicle to update does not exist.
+async fn store_vehicle(db_txn: &Transaction<'_>, id: Option<i64>, company: &str, vehicle: &ExportedVehicle) -> Result<Option<i64>, tokio_postgres::Error> {
+    let bim_id = if let Some(edit_id) = id {
+        let updated_rows = db_txn.execute(
+            "
+                UPDATE bimdb.bims
+                SET
+                    company = $1,
+                    veh_number = $2,
+                    type_code = $3,
+                    veh_class = $4,
+                    in_service_since = $5,
+                    out_of_service_since = $6,
+                    manufacturer = $7,
+                    depot = $8,
+                    other_data = $9
+                WHERE
+                    id = $10
+                    AND deleted_at IS NULL
+            ",
+            &[
+                &company, &vehicle.number, &vehicle.type_code, &vehicle.vehicle_class,
+                &vehicle.in_service_since, &vehicle.out_of_service_since, &vehicle.manufacturer, &vehicle.depot,
+                &vehicle.other_data,
+                &edit_id,
+            ],
+        ).await?;
+        if updated_rows == 0 {
+            return Ok(None);
+        }
+        edit_id
+    } else {
+        let row = db_txn.query_one(
+            "
+                INSERT INTO bimdb.bims
+                    (
+                        id,
+                        company, veh_number, type_code, veh_class,
+                        in_service_since, out_of_service_since, manufacturer, depot,
+                        other_data
+                    )
+                VALUES
+                    (
+                        DEFAULT,
+                        $1, $2, $3, $4,
+                        $5, $6, $7, $8,
+                        $9
+                    )
+                RETURNING id
+            ",
+            &[
+                &company, &vehicle.number, &vehicle.type_code, &vehicle.vehicle_class,
+                &vehicle.in_service_since, &vehicle.out_of_service_since, &vehicle.manufacturer, &vehicle.depot,
+                &vehicle.other_data,
+            ],
+        ).await?;
+        row.get(0)
+    };
+
+    // replace power sources
+    db_txn.execute(
+        "DELETE FROM bimdb.power_sources WHERE bim_id = $1",
+        &[&bim_id],
+    ).await?;
+    let insert_stmt = db_txn.prepare(
+        "INSERT INTO bimdb.power_sources (bim_id, power_source) VALUES ($1, $2)",
+    ).await?;
+    for power_source in &vehicle.power_sources {
+        db_txn.execute(&insert_stmt, &[&bim_id, &power_source.as_str()]).await?;
+    }
+
+    Ok(Some(bim_id))
+}
+
+/// A reason why a coupling could not be stored.
+#[derive(Debug)]
+enum StoreCouplingError {
+    Database(tokio_postgres::Error),
+    /// The vehicle with this ID is listed more than once.
+    DuplicateVehicle(i64),
+    /// Fewer than two vehicles are listed.
+    TooFewVehicles,
+    /// There is no vehicle with this ID.
+    UnknownVehicle(i64),
+    /// The vehicles belong to different companies.
+    MixedCompanies(String, String),
+    /// A vehicle is already part of a different coupling.
+    AlreadyCoupled { vehicle_id: i64, company: String, veh_number: String, coupling_id: i64 },
+}
+impl From<tokio_postgres::Error> for StoreCouplingError {
+    fn from(e: tokio_postgres::Error) -> Self { Self::Database(e) }
+}
+
+/// Inserts (if `id` is `None`) or replaces a coupling consisting of the given vehicles in order.
+///
+/// Returns the ID of the coupling, or `None` if the coupling to replace does not exist.
+async fn store_coupling(db_txn: &Transaction<'_>, id: Option<i64>, vehicle_ids: &[i64]) -> Result<Option<i64>, StoreCouplingError> {
+    for (i, vehicle_id) in vehicle_ids.iter().enumerate() {
+        if vehicle_ids[..i].contains(vehicle_id) {
+            return Err(StoreCouplingError::DuplicateVehicle(*vehicle_id));
+        }
+    }
+    if vehicle_ids.len() < 2 {
+        return Err(StoreCouplingError::TooFewVehicles);
+    }
+
+    if let Some(edit_id) = id {
+        let coupling_row_opt = db_txn.query_opt(
+            "SELECT id FROM bimdb.couplings WHERE id = $1 FOR UPDATE",
+            &[&edit_id],
+        ).await?;
+        if coupling_row_opt.is_none() {
+            return Ok(None);
+        }
+    }
+
+    // the trigger enforces this too, but only with an opaque error message
+    let vehicle_rows = db_txn.query(
+        "SELECT id, company FROM bimdb.bims WHERE id = ANY($1) AND deleted_at IS NULL",
+        &[&vehicle_ids],
+    ).await?;
+    let vehicle_companies: HashMap<i64, String> = vehicle_rows.iter()
+        .map(|r| (r.get(0), r.get(1)))
+        .collect();
+    let mut coupling_company: Option<&String> = None;
+    for vehicle_id in vehicle_ids {
+        let company = match vehicle_companies.get(vehicle_id) {
+            Some(c) => c,
+            None => return Err(StoreCouplingError::UnknownVehicle(*vehicle_id)),
+        };
+        match coupling_company {
+            None => coupling_company = Some(company),
+            Some(cc) if cc != company => return Err(StoreCouplingError::MixedCompanies(cc.clone(), company.clone())),
+            Some(_) => {},
+        }
+    }
+
+    // find vehicles already in another coupling before the primary key does
+    let coupled_row_opt = db_txn.query_opt(
+        "
+            SELECT cb.bim_id, b.company, b.veh_number, cb.coupling_id
+            FROM bimdb.coupling_bims cb
+            INNER JOIN bimdb.bims b ON b.id = cb.bim_id
+            WHERE cb.bim_id = ANY($1)
+            AND ($2::bigint IS NULL OR cb.coupling_id <> $2)
+            ORDER BY b.veh_number, cb.bim_id
+            LIMIT 1
+        ",
+        &[&vehicle_ids, &id],
+    ).await?;
+    if let Some(coupled_row) = coupled_row_opt {
+        return Err(StoreCouplingError::AlreadyCoupled {
+            vehicle_id: coupled_row.get(0),
+            company: coupled_row.get(1),
+            veh_number: coupled_row.get(2),
+            coupling_id: coupled_row.get(3),
+        });
+    }
+
+    let coupling_id = if let Some(edit_id) = id {
+        // delete (and then reinsert) entries
+        db_txn.execute("DELETE FROM bimdb.coupling_bims WHERE coupling_id = $1", &[&edit_id]).await?;
+        edit_id
+    } else {
+        let insert_row = db_txn.query_one("INSERT INTO bimdb.couplings (id) VALUES (DEFAULT) RETURNING id", &[]).await?;
+        insert_row.get(0)
     };
 
-    Response::builder()
-        .status(200)
-        .header("Content-Type", content_type)
-        .body(Full::new(Bytes::from(data)))
-        .unwrap_or_else(|_| return_500())
+    let insert_stmt = db_txn.prepare("INSERT INTO bimdb.coupling_bims (bim_id, coupling_id, position) VALUES ($1, $2, $3)").await?;
+    for (i, vehicle_id) in vehicle_ids.iter().enumerate() {
+        let position: i64 = (i + 1).try_into().unwrap();
+        db_txn.execute(&insert_stmt, &[vehicle_id, &coupling_id, &position]).await?;
+    }
+
+    Ok(Some(coupling_id))
 }
 
 #[instrument(skip_all)]
-async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, edit: bool) -> Response<Full<Bytes>> {
+async fn handle_add_edit(remote_addr: SocketAddr, request: Request<Incoming>, edit: bool) -> Response<Full<Bytes>> {
     let query_pairs = match get_query_pairs(request.uri().query()) {
         Some(qp) => qp,
         None => return return_400("invalid UTF-8 in query"),
     };
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
 
     let edit_id_opt = if edit {
         let edit_id_str_opt = query_pairs.iter()
             .filter(|(k, _v)| k == "id")
-            .map(|(_k, v)| v)
-            .flatten()
+            .flat_map(|(_k, v)| v)
             .last();
         let edit_id_str = match edit_id_str_opt {
             Some(eis) => eis,
@@ -637,19 +1881,17 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
     };
 
     let mut db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
     };
 
-    let (base_path, allowed_veh_classes, allowed_power_sources)= {
-        let config = CONFIG
-            .get().expect("CONFIG not set?!");
-        (
-            &config.http.base_path,
-            config.value_sets.vehicle_classes.clone(),
-            config.value_sets.power_sources.clone(),
-        )
+    let value_sets = match value_sets::load_value_sets(&db_conn).await {
+        Some(vs) => vs,
+        None => return return_500(),
     };
+
+    let config = config::current();
+    let base_path = &config.http.base_path;
     if request.method() == Method::GET {
         let template = if let Some(edit_id) = edit_id_opt {
             // find entry
@@ -663,6 +1905,7 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
                         bimdb.bims
                     WHERE
                         id = $1
+                        AND deleted_at IS NULL
                 ",
                 &[&edit_id],
             ).await;
@@ -673,11 +1916,14 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
                     return return_500();
                 },
             };
-            if found_rows.len() == 0 {
+            if found_rows.is_empty() {
                 return return_400("failed to find this vehicle");
             }
 
             let company: String = found_rows[0].get(0);
+            if !principal.may_edit(&company) {
+                return return_403(&format!("you may not edit vehicles of company {:?}", company));
+            }
             let veh_number: String = found_rows[0].get(1);
             let type_code: String = found_rows[0].get(2);
             let vehicle_class: String = found_rows[0].get(3);
@@ -711,38 +1957,49 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
                 power_sources.insert(power_source);
             }
 
+            let selected_power_sources: Vec<&str> = power_sources.iter()
+                .map(|ps| ps.as_str())
+                .collect();
+            let veh_class_options = value_sets.veh_classes.options(Some(&company), &[&vehicle_class]);
+            let power_source_options = value_sets.power_sources.options(Some(&company), &selected_power_sources);
+            let depot_options = value_sets.depots.options(Some(&company), depot.as_deref().as_slice());
+            let manufacturer_options = value_sets.manufacturers.options(Some(&company), manufacturer.as_deref().as_slice());
+
             AddEditTemplate {
                 base_path: base_path.clone(),
                 edit_id: Some(edit_id),
                 company: Some(company),
                 veh_number: Some(veh_number),
                 type_code: Some(type_code),
-                veh_class: Some(vehicle_class),
-                power_sources,
                 in_service_since,
                 out_of_service_since,
                 manufacturer,
                 depot,
                 other_data: Some(serde_json::to_string_pretty(&other_data).expect("failed to stringify other data JSON")),
-                allowed_veh_classes,
-                allowed_power_sources,
+                veh_class_options,
+                power_source_options,
+                depot_options,
+                manufacturer_options,
             }
         } else {
+            if !principal.may_edit_any() {
+                return return_403("you may not add vehicles");
+            }
             AddEditTemplate {
                 base_path: base_path.clone(),
                 edit_id: None,
                 company: None,
                 veh_number: None,
                 type_code: None,
-                veh_class: None,
-                power_sources: BTreeSet::new(),
                 in_service_since: None,
                 out_of_service_since: None,
                 manufacturer: None,
                 depot: None,
                 other_data: None,
-                allowed_veh_classes,
-                allowed_power_sources,
+                veh_class_options: value_sets.veh_classes.options(None, &[]),
+                power_source_options: value_sets.power_sources.options(None, &[]),
+                depot_options: value_sets.depots.options(None, &[]),
+                manufacturer_options: value_sets.manufacturers.options(None, &[]),
             }
         };
 
@@ -767,7 +2024,7 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
             .collect();
 
         let company = match form_values.get_last("company") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'company' must not be empty");
             } else {
                 c
@@ -775,7 +2032,7 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
             None => return return_400("field 'company' is required"),
         };
         let vehicle_number = match form_values.get_last("veh-number") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'veh-number' must not be empty");
             } else {
                 c
@@ -783,7 +2040,7 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
             None => return return_400("field 'veh-number' is required"),
         };
         let type_code = match form_values.get_last("type-code") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'type-code' must not be empty");
             } else {
                 c
@@ -791,7 +2048,7 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
             None => return return_400("field 'type-code' is required"),
         };
         let vehicle_class = match form_values.get_last("veh-class") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'veh-class' must not be empty");
             } else {
                 c
@@ -802,55 +2059,53 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
         for power_source_value in form_values.get_list_or_empty("power-source") {
             for line in power_source_value.split("\n") {
                 let trimmed_line = line.trim();
-                if trimmed_line.len() == 0 {
+                if trimmed_line.is_empty() {
                     continue;
                 }
                 power_sources.insert(trimmed_line.to_owned());
             }
         }
         let in_service_since = form_values.get_last("in-service-since")
-            .and_then(|c| if c.len() == 0 { None } else { Some(c) });
+            .and_then(|c| if c.is_empty() { None } else { Some(c) });
         let out_of_service_since = form_values.get_last("out-of-service-since")
-            .and_then(|c| if c.len() == 0 { None } else { Some(c) });
+            .and_then(|c| if c.is_empty() { None } else { Some(c) });
         let manufacturer = form_values.get_last("manufacturer")
-            .and_then(|c| if c.len() == 0 { None } else { Some(c) });
+            .and_then(|c| if c.is_empty() { None } else { Some(c) });
         let depot = form_values.get_last("depot")
-            .and_then(|c| if c.len() == 0 { None } else { Some(c) });
+            .and_then(|c| if c.is_empty() { None } else { Some(c) });
         let other_data_string = match form_values.get_last("other-data") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'other-data' must not be empty");
             } else {
                 c
             },
             None => return return_400("field 'other-data' is required"),
         };
-        let other_data: serde_json::Value = match serde_json::from_str(&other_data_string) {
+        let other_data: serde_json::Value = match serde_json::from_str(other_data_string) {
             Ok(od) => od,
             Err(e) => {
                 error!("failed to parse other data: {}", e);
                 return return_400("field 'other-data' is not valid JSON");
             },
         };
-        if !other_data.is_object() {
-            return return_400("field 'other-data' does not contain a JSON object");
-        }
 
-        let value_sets = {
-            let config = CONFIG
-                .get().expect("CONFIG not set?!");
-            &config.value_sets
+        let mut vehicle = ExportedVehicle {
+            number: vehicle_number.to_string(),
+            vehicle_class: vehicle_class.to_string(),
+            type_code: type_code.to_string(),
+            in_service_since: in_service_since.map(|v| v.to_string()),
+            out_of_service_since: out_of_service_since.map(|v| v.to_string()),
+            manufacturer: manufacturer.map(|v| v.to_string()),
+            depot: depot.map(|v| v.to_string()),
+            other_data,
+            fixed_coupling: Vec::with_capacity(0),
+            power_sources,
         };
-        if value_sets.vehicle_classes.len() > 0 {
-            if !value_sets.vehicle_classes.contains(vehicle_class.as_ref()) {
-                return return_400("field 'veh-class' is not one of the allowed values");
-            }
+        if let Err(e) = ExportedVehicle::validate_company(company) {
+            return return_400(&e);
         }
-        if value_sets.power_sources.len() > 0 {
-            for power_source in &power_sources {
-                if !value_sets.power_sources.contains(power_source) {
-                    return return_400("one of the 'power-source' values is not one of the allowed values");
-                }
-            }
+        if !principal.may_edit(company) {
+            return return_403(&format!("you may not edit vehicles of company {:?}", company));
         }
 
         let transact = match db_conn.transaction().await {
@@ -860,98 +2115,43 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
                 return return_500();
             },
         };
+        if let Err(e) = set_change_context(&transact, Some(&principal), Some(remote_addr)).await {
+            error!("failed to set change context: {}", e);
+            return return_500();
+        }
 
-        let bim_id = if let Some(edit_id) = edit_id_opt {
-            let update_res = transact.execute(
-                "
-                    UPDATE bimdb.bims
-                    SET
-                        company = $1,
-                        veh_number = $2,
-                        type_code = $3,
-                        veh_class = $4,
-                        in_service_since = $5,
-                        out_of_service_since = $6,
-                        manufacturer = $7,
-                        depot = $8,
-                        other_data = $9
-                    WHERE
-                        id = $10
-                ",
-                &[
-                    &company, &vehicle_number, &type_code, &vehicle_class,
-                    &in_service_since, &out_of_service_since, &manufacturer, &depot,
-                    &other_data,
-                    &edit_id,
-                ],
-            ).await;
-            if let Err(e) = update_res {
-                error!("failed to update vehicle {}: {}", edit_id, e);
-                return return_500();
-            }
-            edit_id
-        } else {
-            let insert_res = transact.query_one(
-                "
-                    INSERT INTO bimdb.bims
-                        (
-                            id,
-                            company, veh_number, type_code, veh_class,
-                            in_service_since, out_of_service_since, manufacturer, depot,
-                            other_data
-                        )
-                    VALUES
-                        (
-                            DEFAULT,
-                            $1, $2, $3, $4,
-                            $5, $6, $7, $8,
-                            $9
-                        )
-                    RETURNING id
-                ",
-                &[
-                    &company, &vehicle_number, &type_code, &vehicle_class,
-                    &in_service_since, &out_of_service_since, &manufacturer, &depot,
-                    &other_data,
-                ],
-            ).await;
-            match insert_res {
-                Ok(row) => {
-                    let inserted_id: i64 = row.get(0);
-                    inserted_id
-                },
+        if let Some(edit_id) = edit_id_opt {
+            let old_company = match lock_vehicle_company(&transact, edit_id).await {
+                Ok(Some(oc)) => oc,
+                Ok(None) => return return_400("failed to find this vehicle"),
                 Err(e) => {
-                    error!("failed to insert vehicle: {}", e);
+                    error!("failed to obtain company of existing vehicle {}: {}", edit_id, e);
                     return return_500();
                 },
+            };
+            if !principal.may_edit(&old_company) {
+                return return_403(&format!("you may not edit vehicles of company {:?}", old_company));
             }
-        };
+        }
 
-        // replace power sources
-        let delete_power_sources_res = transact.execute(
-            "DELETE FROM bimdb.power_sources WHERE bim_id = $1",
-            &[&bim_id],
-        ).await;
-        if let Err(e) = delete_power_sources_res {
-            error!("failed to delete power sources for {}: {}", bim_id, e);
-            return return_500();
+        let stored = match edit_id_opt {
+            Some(edit_id) => match load_stored_vehicle(&transact, edit_id, company).await {
+                Some(s) => s,
+                None => return return_500(),
+            },
+            None => None,
+        };
+        if let Err(e) = vehicle.validate(company, &value_sets, stored.as_ref()) {
+            return return_400(&e);
         }
 
-        let insert_stmt_res = transact.prepare(
-            "INSERT INTO bimdb.power_sources (bim_id, power_source) VALUES ($1, $2)",
-        ).await;
-        let insert_stmt = match insert_stmt_res {
-            Ok(is) => is,
+        match store_vehicle(&transact, edit_id_opt, company, &vehicle).await {
+            Ok(Some(_bim_id)) => {},
+            Ok(None) => return return_400("failed to find this vehicle"),
             Err(e) => {
-                error!("failed to prepare insert-power-source statement: {}", e);
+                error!("failed to store vehicle (edit ID {:?}): {}", edit_id_opt, e);
                 return return_500();
             },
-        };
-        for power_source in &power_sources {
-            if let Err(e) = transact.execute(&insert_stmt, &[&bim_id, &power_source.as_str()]).await {
-                error!("failed to insert power source {:?} for {}: {}", power_source, bim_id, e);
-                return return_500();
-            }
         }
 
         if let Err(e) = transact.commit().await {
@@ -959,7 +2159,7 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
             return return_500();
         }
 
-        let base_path_or_slash = if base_path.len() == 0 { "/" } else { base_path };
+        let base_path_or_slash = if base_path.is_empty() { "/" } else { base_path };
         Response::builder()
             .status(302)
             .header("Location", base_path_or_slash)
@@ -971,21 +2171,72 @@ async fn handle_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, e
     }
 }
 
+/// Renders the page asking whether a coupled vehicle should be removed from its coupling and then
+/// deleted.
+async fn render_delete_confirm(db_txn: &Transaction<'_>, id: i64, company: &str, coupling_id: i64) -> Response<Full<Bytes>> {
+    let vehicle_rows_res = db_txn.query(
+        "
+            SELECT b.id, b.veh_number
+            FROM bimdb.coupling_bims cb
+            INNER JOIN bimdb.bims b ON b.id = cb.bim_id
+            WHERE cb.coupling_id = $1
+            ORDER BY cb.position
+        ",
+        &[&coupling_id],
+    ).await;
+    let vehicle_rows = match vehicle_rows_res {
+        Ok(vr) => vr,
+        Err(e) => {
+            error!("failed to obtain vehicles of coupling {}: {}", coupling_id, e);
+            return return_500();
+        },
+    };
+    let mut veh_number = String::new();
+    let mut coupling_numbers = Vec::with_capacity(vehicle_rows.len());
+    for row in vehicle_rows {
+        let bim_id: i64 = row.get(0);
+        let number: String = row.get(1);
+        if bim_id == id {
+            veh_number = number.clone();
+        }
+        coupling_numbers.push(number);
+    }
+
+    let template = DeleteConfirmTemplate {
+        base_path: config::current().http.base_path.clone(),
+        id,
+        company: company.to_owned(),
+        veh_number,
+        coupling_id,
+        coupling_numbers,
+    };
+    let template_text = template.render()
+        .expect("failed to render template");
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Full::new(Bytes::from(template_text)))
+        .unwrap_or_else(|_| return_500())
+}
+
 #[instrument(skip_all)]
-async fn handle_delete(_remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+async fn handle_delete(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
     let query_pairs = match get_query_pairs(request.uri().query()) {
         Some(qp) => qp,
         None => return return_400("invalid UTF-8 in query"),
     };
 
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+
     if request.method() != Method::POST {
         return return_405(request.method(), &[Method::POST]);
     }
 
     let delete_id_str_opt = query_pairs.iter()
         .filter(|(k, _v)| k == "id")
-        .map(|(_k, v)| v)
-        .flatten()
+        .flat_map(|(_k, v)| v)
         .last();
     let delete_id_str = match delete_id_str_opt {
         Some(eis) => eis,
@@ -995,31 +2246,66 @@ async fn handle_delete(_remote_addr: SocketAddr, request: Request<Incoming>) ->
         Ok(ei) => ei,
         Err(_) => return return_400("invalid parameter value for 'id'"),
     };
+    // "uncouple" removes the vehicle from its coupling first
+    let uncouple = query_pairs.iter()
+        .any(|(k, _v)| k == "uncouple");
 
-    let db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to create database transaction: {}", e);
+            return return_500();
+        },
     };
+    if let Err(e) = set_change_context(&db_txn, Some(&principal), Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return return_500();
+    }
 
-    // delete entry
-    let affected_rows_res = db_conn.execute(
-        "DELETE FROM bimdb.bims WHERE id = $1",
-        &[&delete_id],
-    ).await;
-    let affected_rows = match affected_rows_res {
-        Ok(ar) => ar,
+    let company = match lock_vehicle_company(&db_txn, delete_id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return return_400("failed to find this vehicle"),
         Err(e) => {
-            error!("failed to delete vehicle {}: {}", delete_id, e);
+            error!("failed to obtain company of vehicle {}: {}", delete_id, e);
             return return_500();
         },
     };
-    if affected_rows == 0 {
-        return return_400("failed to find this vehicle");
+    if !principal.may_edit(&company) {
+        return return_403(&format!("you may not delete vehicles of company {:?}", company));
+    }
+
+    if uncouple {
+        if let Err(e) = uncouple_vehicle(&db_txn, delete_id).await {
+            error!("failed to remove vehicle {} from its coupling: {}", delete_id, e);
+            return return_500();
+        }
+    }
+
+    // move entry into the trash
+    match trash_vehicle(&db_txn, delete_id, Some(&principal)).await {
+        Ok(()) => {},
+        Err(TrashVehicleError::Coupled(coupling_id)) => {
+            // ask the user whether to remove the vehicle from its coupling
+            return render_delete_confirm(&db_txn, delete_id, &company, coupling_id).await;
+        },
+        Err(TrashVehicleError::Database(e)) => {
+            error!("failed to delete vehicle {}: {}", delete_id, e);
+            return return_500();
+        },
+    }
+
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit vehicle deletion transaction: {}", e);
+        return return_500();
     }
 
-    let base_path = &CONFIG.get().expect("CONFIG not set?!")
+    let base_path = &config::current()
         .http.base_path;
-    let base_path_or_slash = if base_path.len() == 0 { "/" } else { base_path };
+    let base_path_or_slash = if base_path.is_empty() { "/" } else { base_path };
     Response::builder()
         .status(302)
         .header("Location", base_path_or_slash)
@@ -1033,27 +2319,38 @@ async fn handle_couplings(_remote_addr: SocketAddr, request: Request<Incoming>)
         return return_405(request.method(), &[Method::GET]);
     }
 
+    let principal = auth::principal(&request);
+    let view_scope = auth::view_scope(principal.as_ref());
+
     let db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
     };
 
-    // obtain couplings
-    let coupling_rows_res = db_conn.query(
+    // obtain couplings; all vehicles of a coupling belong to the same company
+    let mut company_params = Vec::new();
+    let coupling_query = format!(
         "
             SELECT
-                c.id, JSONB_AGG(JSONB_BUILD_OBJECT('id', b.id, 'veh_number', b.veh_number) ORDER BY cb.position) vehicles
+                c.id, JSONB_AGG(JSONB_BUILD_OBJECT('id', b.id, 'veh_number', b.veh_number) ORDER BY cb.position) vehicles,
+                MIN(b.company) company
             FROM
                 bimdb.couplings c
                 INNER JOIN bimdb.coupling_bims cb ON cb.coupling_id = c.id
                 INNER JOIN bimdb.bims b ON b.id = cb.bim_id
+            WHERE
+                {}
             GROUP BY
                 c.id
             ORDER BY
                 c.id
         ",
-        &[],
-    ).await;
+        view_scope.sql_condition("b.company", &mut company_params),
+    );
+    let company_param_refs: Vec<&(dyn ToSql + Sync)> = company_params.iter()
+        .map(|c| c as &(dyn ToSql + Sync))
+        .collect();
+    let coupling_rows_res = db_conn.query(&coupling_query, &company_param_refs).await;
     let coupling_rows = match coupling_rows_res {
         Ok(vr) => vr,
         Err(e) => {
@@ -1066,6 +2363,7 @@ async fn handle_couplings(_remote_addr: SocketAddr, request: Request<Incoming>)
     for row in coupling_rows {
         let id: i64 = row.get(0);
         let vehicles_json: serde_json::Value = row.get(1);
+        let company: String = row.get(2);
 
         let vehicles: Vec<CouplingVehiclePart> = serde_json::from_value(vehicles_json)
             .expect("coupling not deserializable into CouplingVehiclePart");
@@ -1073,13 +2371,15 @@ async fn handle_couplings(_remote_addr: SocketAddr, request: Request<Incoming>)
         couplings.push(CouplingPart {
             id,
             vehicles,
+            editable: auth::may_edit(principal.as_ref(), &company),
         })
     }
 
-    let config = CONFIG.get().expect("CONFIG not set?!");
+    let config = config::current();
     let template = CouplingListTemplate {
         base_path: config.http.base_path.clone(),
         couplings,
+        may_add: principal.map(|p| p.may_edit_any()).unwrap_or(false),
     };
     let template_text = template.render()
         .expect("failed to render template");
@@ -1091,17 +2391,19 @@ async fn handle_couplings(_remote_addr: SocketAddr, request: Request<Incoming>)
 }
 
 #[instrument(skip_all)]
-async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Incoming>, edit: bool) -> Response<Full<Bytes>> {
+async fn handle_coupling_add_edit(remote_addr: SocketAddr, request: Request<Incoming>, edit: bool) -> Response<Full<Bytes>> {
     let query_pairs = match get_query_pairs(request.uri().query()) {
         Some(qp) => qp,
         None => return return_400("invalid UTF-8 in query"),
     };
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
 
     let edit_id_opt = if edit {
         let edit_id_str_opt = query_pairs.iter()
             .filter(|(k, _v)| k == "id")
-            .map(|(_k, v)| v)
-            .flatten()
+            .flat_map(|(_k, v)| v)
             .last();
         let edit_id_str = match edit_id_str_opt {
             Some(eis) => eis,
@@ -1117,12 +2419,12 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
     };
 
     let mut db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
     };
 
     let mut company_to_vehicles = BTreeMap::new();
-    let vehicle_rows = match db_conn.query("SELECT company, veh_number FROM bimdb.bims", &[]).await {
+    let vehicle_rows = match db_conn.query("SELECT company, veh_number FROM bimdb.bims WHERE deleted_at IS NULL", &[]).await {
         Ok(r) => r,
         Err(e) => {
             error!("failed to obtain list of uncoupled vehicles: {}", e);
@@ -1133,14 +2435,19 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
         let company: String = row.get(0);
         let veh_number: String = row.get(1);
 
+        if !principal.may_edit(&company) {
+            continue;
+        }
         company_to_vehicles
             .entry(company)
-            .or_insert_with(|| BTreeSet::new())
-            .insert(veh_number);
+            .or_insert_with(Vec::new)
+            .push(veh_number);
+    }
+    for vehicles in company_to_vehicles.values_mut() {
+        vehicles.sort_by(|l, r| natural_sort::compare_vehicle_numbers(l, r));
     }
 
-    let base_path = &CONFIG
-        .get().expect("CONFIG not set?!")
+    let base_path = &config::current()
         .http.base_path;
     if request.method() == Method::GET {
         let template = if let Some(edit_id) = edit_id_opt {
@@ -1156,7 +2463,7 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
                     return return_500();
                 },
             };
-            if found_rows.len() == 0 {
+            if found_rows.is_empty() {
                 return return_400("failed to find this coupling");
             }
 
@@ -1184,6 +2491,9 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
                 let veh_company: String = vehicle_row.get(0);
                 let veh_number: String = vehicle_row.get(1);
 
+                if !principal.may_edit(&veh_company) {
+                    return return_403(&format!("you may not edit couplings of company {:?}", veh_company));
+                }
                 company = Some(veh_company);
                 vehicles.push(veh_number);
             };
@@ -1196,6 +2506,9 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
                 vehicles,
             }
         } else {
+            if !principal.may_edit_any() {
+                return return_403("you may not add couplings");
+            }
             CouplingAddEditTemplate {
                 base_path: base_path.clone(),
                 edit_id: None,
@@ -1226,16 +2539,19 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
             .collect();
 
         let company = match form_values.get("company") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'company' must not be empty");
             } else {
                 c
             },
             None => return return_400("field 'company' is required"),
         };
+        if !principal.may_edit(company) {
+            return return_403(&format!("you may not edit couplings of company {:?}", company));
+        }
 
         let vehicles_str = match form_values.get("vehicles") {
-            Some(c) => if c.len() == 0 {
+            Some(c) => if c.is_empty() {
                 return return_400("field 'vehicles' must not be empty");
             } else {
                 c
@@ -1244,11 +2560,14 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
         };
         let vehicle_numbers: Vec<&str> = vehicles_str.split('\n')
             .map(|veh| veh.trim())
-            .filter(|veh| veh.len() > 0)
+            .filter(|veh| !veh.is_empty())
             .collect();
+        if vehicle_numbers.len() < 2 {
+            return return_400("a coupling must contain at least two vehicles");
+        }
 
         // ensure that all vehicles exist
-        let select_vehicle_stmt_res = db_conn.prepare("SELECT id FROM bimdb.bims WHERE company = $1 AND veh_number = $2").await;
+        let select_vehicle_stmt_res = db_conn.prepare("SELECT id FROM bimdb.bims WHERE company = $1 AND veh_number = $2 AND deleted_at IS NULL").await;
         let select_vehicle_stmt = match select_vehicle_stmt_res {
             Ok(svs) => svs,
             Err(e) => {
@@ -1275,7 +2594,7 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
             let vehicle_id: i64 = row.get(0);
             vehicle_ids.push(vehicle_id);
         }
-        if unknown_vehicle_numbers.len() > 0 {
+        if !unknown_vehicle_numbers.is_empty() {
             let mut error_message = "unknown vehicle numbers:".to_owned();
             for uvn in unknown_vehicle_numbers {
                 error_message.push_str(uvn);
@@ -1290,42 +2609,47 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
                 return return_500();
             },
         };
-        let insert_stmt = match db_txn.prepare("INSERT INTO bimdb.coupling_bims (bim_id, coupling_id, position) VALUES ($1, $2, $3)").await {
-            Ok(is) => is,
-            Err(e) => {
-                error!("failed to create insert-coupling-bim statement: {}", e);
-                return return_500();
-            },
-        };
-
-        let coupling_id = if let Some(edit_id) = edit_id_opt {
-            // delete (and then reinsert) entries
-            if let Err(e) = db_txn.execute("DELETE FROM bimdb.coupling_bims WHERE coupling_id = $1", &[&edit_id]).await {
-                error!("failed to delete bims of coupling {}: {}", edit_id, e);
-                return return_500();
-            }
-
-            edit_id
-        } else {
-            // add new coupling
-            let insert_row = match db_txn.query_one("INSERT INTO bimdb.couplings (id) VALUES (DEFAULT) RETURNING id", &[]).await {
-                Ok(r) => r,
+        if let Err(e) = set_change_context(&db_txn, Some(&principal), Some(remote_addr)).await {
+            error!("failed to set change context: {}", e);
+            return return_500();
+        }
+        if let Some(edit_id) = edit_id_opt {
+            let old_company_opt = match lock_coupling_company(&db_txn, edit_id).await {
+                Ok(Some(oc)) => oc,
+                Ok(None) => return return_400("failed to find this coupling"),
                 Err(e) => {
-                    error!("error inserting new coupling: {}", e);
+                    error!("failed to obtain company of existing coupling {}: {}", edit_id, e);
                     return return_500();
                 },
             };
-            insert_row.get(0)
-        };
-
-        for (i, vehicle_id) in vehicle_ids.into_iter().enumerate() {
-            let position: i64 = (i + 1).try_into().unwrap();
-
-            if let Err(e) = db_txn.execute(&insert_stmt, &[&vehicle_id, &coupling_id, &position]).await {
-                error!("failed to insert bim {} into coupling {} at position {}: {}", vehicle_id, coupling_id, position, e);
-                return return_500();
+            if let Some(old_company) = old_company_opt {
+                if !principal.may_edit(&old_company) {
+                    return return_403(&format!("you may not edit couplings of company {:?}", old_company));
+                }
             }
         }
+        let coupling_id = match store_coupling(&db_txn, edit_id_opt, &vehicle_ids).await {
+            Ok(Some(ci)) => ci,
+            Ok(None) => return return_400("failed to find this coupling"),
+            Err(StoreCouplingError::DuplicateVehicle(_)) => return return_400("a vehicle is listed more than once"),
+            Err(StoreCouplingError::TooFewVehicles) => return return_400("a coupling must contain at least two vehicles"),
+            Err(StoreCouplingError::UnknownVehicle(vehicle_id)) => {
+                return return_400(&format!("vehicle {} no longer exists", vehicle_id));
+            },
+            Err(StoreCouplingError::MixedCompanies(_, _)) => {
+                return return_400("all vehicles in a coupling must belong to the same company");
+            },
+            Err(StoreCouplingError::AlreadyCoupled { veh_number, coupling_id, .. }) => {
+                return return_409(&format!("vehicle {} is already part of coupling {}", veh_number, coupling_id));
+            },
+            Err(StoreCouplingError::Database(e)) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                return return_409("a vehicle is already part of another coupling");
+            },
+            Err(StoreCouplingError::Database(e)) => {
+                error!("failed to store coupling {:?}: {}", edit_id_opt, e);
+                return return_500();
+            },
+        };
 
         if let Err(e) = db_txn.commit().await {
             error!("failed to commit insertion/replacement of vehicles in coupling {}: {}", coupling_id, e);
@@ -1345,20 +2669,23 @@ async fn handle_coupling_add_edit(_remote_addr: SocketAddr, request: Request<Inc
 }
 
 #[instrument(skip_all)]
-async fn handle_coupling_delete(_remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+async fn handle_coupling_delete(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
     let query_pairs = match get_query_pairs(request.uri().query()) {
         Some(qp) => qp,
         None => return return_400("invalid UTF-8 in query"),
     };
 
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+
     if request.method() != Method::POST {
         return return_405(request.method(), &[Method::POST]);
     }
 
     let delete_id_str_opt = query_pairs.iter()
         .filter(|(k, _v)| k == "id")
-        .map(|(_k, v)| v)
-        .flatten()
+        .flat_map(|(_k, v)| v)
         .last();
     let delete_id_str = match delete_id_str_opt {
         Some(eis) => eis,
@@ -1370,8 +2697,8 @@ async fn handle_coupling_delete(_remote_addr: SocketAddr, request: Request<Incom
     };
 
     let mut db_conn = match db_connect().await {
-        Some(dbc) => dbc,
-        None => return return_500(),
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
     };
     let db_txn = match db_conn.transaction().await {
         Ok(t) => t,
@@ -1380,6 +2707,24 @@ async fn handle_coupling_delete(_remote_addr: SocketAddr, request: Request<Incom
             return return_500();
         },
     };
+    if let Err(e) = set_change_context(&db_txn, Some(&principal), Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return return_500();
+    }
+
+    let company_opt = match lock_coupling_company(&db_txn, delete_id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return return_400("failed to find this coupling"),
+        Err(e) => {
+            error!("failed to obtain company of coupling {}: {}", delete_id, e);
+            return return_500();
+        },
+    };
+    if let Some(company) = company_opt {
+        if !principal.may_edit(&company) {
+            return return_403(&format!("you may not delete couplings of company {:?}", company));
+        }
+    }
 
     // delete vehicles
     let affected_rows_res = db_txn.execute(
@@ -1412,7 +2757,7 @@ async fn handle_coupling_delete(_remote_addr: SocketAddr, request: Request<Incom
         return return_500();
     }
 
-    let base_path = &CONFIG.get().expect("CONFIG not set?!")
+    let base_path = &config::current()
         .http.base_path;
     let redirect_path = format!("{}/couplings", base_path);
     Response::builder()
@@ -1423,41 +2768,151 @@ async fn handle_coupling_delete(_remote_addr: SocketAddr, request: Request<Incom
         .unwrap_or_else(|_| return_500())
 }
 
+fn handle_static(file_name: &str) -> Response<Full<Bytes>> {
+    let static_path_opt = {
+        let config = config::current();
+        config.http.static_path.as_ref().map(PathBuf::from)
+    };
+    let mut static_path = match static_path_opt {
+        Some(sp) => sp,
+        None => return return_404(),
+    };
+    static_path.push(file_name);
+
+    if !static_path.is_file() {
+        return return_404();
+    }
+
+    let contents = match std::fs::read(&static_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("failed to read file {:?}: {}", static_path, e);
+            return return_500();
+        },
+    };
+    let content_type = if file_name.ends_with(".css") {
+        "text/css"
+    } else if file_name.ends_with(".js") {
+        "text/javascript"
+    } else if file_name.ends_with(".js.map") {
+        "application/json"
+    } else if file_name.ends_with(".ts") {
+        "text/x.typescript"
+    } else {
+        "application/octet-stream"
+    };
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", content_type)
+        .body(Full::new(Bytes::from(contents)))
+        .unwrap_or_else(|_| return_500())
+}
+
+/// Reloads the configuration file on behalf of an administrator of all companies.
+#[instrument(skip_all)]
+async fn handle_reload_config(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::POST]);
+    }
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    if !principal.may_administer(auth::ALL_COMPANIES) {
+        return return_403("only administrators of all companies may reload the configuration");
+    }
+
+    info!("{} ({:?}) is reloading the configuration", remote_addr, principal.username);
+    if let Err(e) = config::reload() {
+        error!("failed to reload configuration; keeping the previous one: {}", e);
+        return return_409(&format!("failed to reload configuration; keeping the previous one: {}", e));
+    }
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("configuration reloaded")))
+        .unwrap_or_else(|_| return_500())
+}
+
 #[instrument(skip(request))]
-async fn handle_request(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+async fn handle_request(remote_addr: SocketAddr, mut request: Request<Incoming>) -> Response<ResponseBody> {
     // get base path parts from config
-    let base_path = &CONFIG
-        .get().expect("CONFIG not set?!")
-        .http.base_path;
-    let base_path_parts = match path_to_parts(&base_path, true) {
+    let config = config::current();
+    let base_path = &config.http.base_path;
+    let base_path_parts = match path_to_parts(base_path, true) {
         Some(bpp) => bpp,
         None => {
-            error!("failed to split CONFIG.http.base_path {:?} into parts", base_path);
-            return return_500();
+            error!("failed to split http.base_path {:?} into parts", base_path);
+            return return_500().map(boxed_body);
         },
     };
 
-    // get URL path parts
-    let uri_path_parts = match path_to_parts(request.uri().path(), true) {
+    // get URL path parts (from a copy, as the request is modified below)
+    let uri_path = request.uri().path().to_owned();
+    let uri_path_parts = match path_to_parts(&uri_path, true) {
         Some(upp) => upp,
         None => {
-            warn!("failed to split URI path {:?} into parts", request.uri().path());
-            return return_400("invalid URI path");
+            warn!("failed to split URI path {:?} into parts", uri_path);
+            return return_400("invalid URI path").map(boxed_body);
         }
     };
 
     let path_parts = match strip_path_prefix(&uri_path_parts, &base_path_parts) {
         Some(pp) => pp,
-        None => return return_400("URI outside of base path"),
+        None => return return_400("URI outside of base path").map(boxed_body),
+    };
+
+    // check whether the user may access this route
+    let principal = match auth::authenticate(path_parts, request.headers()).await {
+        Ok(p) => p,
+        Err(response) => return response.map(boxed_body),
+    };
+    let allowed = match auth::required_access(path_parts, request.method()) {
+        RequiredAccess::Public => true,
+        RequiredAccess::Read => config.auth.public_read || principal.as_ref().map(|p| p.may_view_any()).unwrap_or(false),
+        RequiredAccess::Edit => principal.is_some(),
     };
+    if !allowed {
+        if principal.is_some() {
+            let reason = "you have not been assigned any role";
+            let response = if !path_parts.is_empty() && path_parts[0] == "api" {
+                api::api_error(403, reason)
+            } else {
+                return_403(reason)
+            };
+            return response.map(boxed_body);
+        }
+        return auth::unauthenticated_response(path_parts, &request).map(boxed_body);
+    }
+    if let Some(p) = principal {
+        request.extensions_mut().insert(p);
+    }
 
-    if path_parts.len() == 0 || (path_parts.len() == 1 && path_parts[0].len() == 0) {
+    let response = if path_parts.is_empty() || (path_parts.len() == 1 && path_parts[0].is_empty()) {
         // "/"
         handle_index(remote_addr, request).await
     } else if path_parts.len() == 1 {
         match path_parts[0].as_ref() {
+            "login" => auth::handle_login(remote_addr, request).await,
+            "logout" => auth::handle_logout(remote_addr, request).await,
+            "users" => users::handle_users(remote_addr, request).await,
+            "tokens" => users::handle_tokens(remote_addr, request).await,
+            "trash" => trash::handle_trash(remote_addr, request).await,
+            "value-sets" => value_sets::handle_value_sets(remote_addr, request).await,
+            "reload-config" => handle_reload_config(remote_addr, request).await,
+            "history" => history::handle_history(remote_addr, request, HistorySubject::Vehicle).await,
+            "restore" => history::handle_restore(remote_addr, request, HistorySubject::Vehicle).await,
+            "coupling-history" => history::handle_history(remote_addr, request, HistorySubject::Coupling).await,
+            "coupling-restore" => history::handle_restore(remote_addr, request, HistorySubject::Coupling).await,
             "json" => handle_export(remote_addr, request, ExportFormat::Json).await,
             "cbor" => handle_export(remote_addr, request, ExportFormat::Cbor).await,
+            "csv" => handle_export(remote_addr, request, ExportFormat::Csv).await,
+            "ndjson" => return handle_ndjson_export(remote_addr, request).await,
+            "import" => handle_import(remote_addr, request, ExportFormat::Json).await,
+            "import-cbor" => handle_import(remote_addr, request, ExportFormat::Cbor).await,
+            "import-csv" => handle_import(remote_addr, request, ExportFormat::Csv).await,
+            "import-preview" => handle_import_preview_confirm(remote_addr, request, false).await,
+            "import-confirm" => handle_import_preview_confirm(remote_addr, request, true).await,
             "add" => handle_add_edit(remote_addr, request, false).await,
             "edit" => handle_add_edit(remote_addr, request, true).await,
             "delete" => handle_delete(remote_addr, request).await,
@@ -1467,87 +2922,54 @@ async fn handle_request(remote_addr: SocketAddr, request: Request<Incoming>) ->
             "coupling-delete" => handle_coupling_delete(remote_addr, request).await,
             _ => return_404(),
         }
+    } else if path_parts.len() >= 2 && path_parts[0] == "api" && path_parts[1] == "v1" {
+        let api_path: Vec<String> = path_parts[2..].iter()
+            .map(|pp| pp.to_string())
+            .collect();
+        api::handle_api(remote_addr, request, &api_path).await
     } else if path_parts.len() == 2 && path_parts[0] == "static" && STATIC_FILE_REGEX.is_match(path_parts[1].as_ref()) {
-        let static_path_opt = {
-            let config = CONFIG.get().expect("CONFIG not set?!");
-            config.http.static_path.as_ref().map(|sp| PathBuf::from(sp))
-        };
-        let mut static_path = match static_path_opt {
-            Some(sp) => sp,
-            None => return return_404(),
-        };
-        static_path.push(path_parts[1].as_ref());
-
-        if !static_path.is_file() {
-            return return_404();
-        }
-
-        let contents = match std::fs::read(&static_path) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("failed to read file {:?}: {}", static_path, e);
-                return return_500();
-            },
-        };
-        let content_type = if path_parts[1].ends_with(".css") {
-            "text/css"
-        } else if path_parts[1].ends_with(".js") {
-            "text/javascript"
-        } else if path_parts[1].ends_with(".js.map") {
-            "application/json"
-        } else if path_parts[1].ends_with(".ts") {
-            "text/x.typescript"
-        } else {
-            "application/octet-stream"
-        };
-
-        Response::builder()
-            .status(200)
-            .header("Content-Type", content_type)
-            .body(Full::new(Bytes::from(contents)))
-            .unwrap_or_else(|_| return_500())
+        handle_static(path_parts[1].as_ref())
     } else {
         return_404()
-    }
+    };
+    response.map(boxed_body)
 }
 
 
-#[tokio::main]
-async fn main() -> ExitCode {
-    // enable tracing
-    tracing_subscriber::fmt::init();
-
-    // find config path
-    let args: Vec<OsString> = std::env::args_os().collect();
-    let config_path = if args.len() == 1 {
-        PathBuf::from("config.toml")
-    } else if args.len() == 2 {
-        PathBuf::from(&args[1])
-    } else {
-        eprintln!("Usage: {:?} [CONFIG.TOML]", args[0]);
-        return ExitCode::FAILURE;
-    };
+/// Checks the database schema and serves HTTP requests until the process is terminated.
+async fn serve() -> ExitCode {
+    let config = config::current();
 
-    // load config
-    let config: Config = {
-        let mut f = File::open(&config_path)
-            .expect("failed to open config file");
-        let mut config_bytes = Vec::new();
-        f.read_to_end(&mut config_bytes)
-            .expect("failed to read config file");
-        let config_string = String::from_utf8(config_bytes)
-            .expect("failed to decode config file as UTF-8");
-        toml::from_str(&config_string)
-            .expect("failed to parse config file as TOML")
-    };
-    CONFIG.set(config)
-        .expect("CONFIG already set?!");
-    let config = CONFIG.get()
-        .expect("CONFIG not set?!");
+    // refuse to work with an outdated or newer schema
+    {
+        let db_conn = match DB_POOL.get().expect("DB_POOL not set?!").get().await {
+            Ok(dbc) => dbc,
+            Err(e) => {
+                eprintln!("failed to connect to database: {}", e);
+                return ExitCode::FAILURE;
+            },
+        };
+        if let Err(e) = migrations::check_schema_version(&db_conn).await {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if !config.value_sets.vehicle_classes.is_empty() || !config.value_sets.power_sources.is_empty() {
+        warn!("the value_sets section of the configuration is no longer used; value sets are managed on the value sets page");
+    }
+    auth::prepare_password_verification().await;
+    tokio::spawn(prune_idle_db_connections());
+    #[cfg(unix)]
+    tokio::spawn(reload_config_on_hangup());
 
     // listen to TCP
-    let listener = TcpListener::bind(config.http.listen_socket_addr).await
-        .expect("failed to open listening socket");
+    let listener = match TcpListener::bind(config.http.listen_socket_addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("failed to listen on {}: {}", config.http.listen_socket_addr, e);
+            return ExitCode::FAILURE;
+        },
+    };
 
     loop {
         let (stream, remote_addr) = listener.accept().await
@@ -1567,3 +2989,54 @@ async fn main() -> ExitCode {
         });
     }
 }
+
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
+
+    // enable tracing; keep the standard output of the other commands clean
+    if command == Command::Serve {
+        tracing_subscriber::fmt::init();
+    } else {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    // load config
+    let config_path = cli.legacy_config.unwrap_or(cli.config);
+    if let Err(e) = config::init(&config_path) {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    let config = config::current();
+
+    // set up database pool
+    let pool = match create_db_pool(&config.db) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("failed to set up database connection: {}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+    DB_POOL.set(pool)
+        .expect("DB_POOL already set?!");
+
+    let result = match command {
+        Command::Serve => return serve().await,
+        Command::Migrate => cli::migrate().await,
+        Command::Export { company, format, output } => cli::export(company, format, output).await,
+        Command::Import { file, company, format, dry_run } => cli::import(file, company, format, dry_run).await,
+        Command::CheckConfig { connect } => cli::check_config(connect).await,
+        Command::AddUser { username, role, company } => cli::add_user(username, role, company).await,
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
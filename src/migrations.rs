@@ -0,0 +1,136 @@
+use tokio_postgres::{Client, Transaction};
+use tracing::info;
+
+use crate::config;
+use crate::value_sets;
+
+
+/// The database schema, as set up for a new database.
+const SCHEMA: &str = include_str!("../db/schema.pgsql");
+
+/// The migrations; the migration at index `i` upgrades the schema from version `i + 1` to `i + 2`.
+const MIGRATIONS: [&str; 12] = [
+    include_str!("../db/migrations/r0001_to_r0002.pgsql"),
+    include_str!("../db/migrations/r0002_to_r0003.pgsql"),
+    include_str!("../db/migrations/r0003_to_r0004.pgsql"),
+    include_str!("../db/migrations/r0004_to_r0005.pgsql"),
+    include_str!("../db/migrations/r0005_to_r0006.pgsql"),
+    include_str!("../db/migrations/r0006_to_r0007.pgsql"),
+    include_str!("../db/migrations/r0007_to_r0008.pgsql"),
+    include_str!("../db/migrations/r0008_to_r0009.pgsql"),
+    include_str!("../db/migrations/r0009_to_r0010.pgsql"),
+    include_str!("../db/migrations/r0010_to_r0011.pgsql"),
+    include_str!("../db/migrations/r0011_to_r0012.pgsql"),
+    include_str!("../db/migrations/r0012_to_r0013.pgsql"),
+];
+
+/// The schema version expected by this version of the code.
+pub(crate) const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64 + 1;
+
+/// The schema version which moved the value sets from the configuration file into the database.
+const VALUE_SETS_SCHEMA_VERSION: i64 = 12;
+
+/// Key of the advisory lock which serializes concurrent migration runs.
+const MIGRATION_LOCK_KEY: i64 = 0x6269_6d64_625f_6d69;
+
+
+/// Returns the schema version of the database, or `None` if the schema has not been set up yet.
+pub(crate) async fn schema_version(db_conn: &Client) -> Result<Option<i64>, tokio_postgres::Error> {
+    let exists_row = db_conn.query_one(
+        "SELECT EXISTS (SELECT 1 FROM information_schema.tables WHERE table_schema = 'bimdb' AND table_name = 'schema_version')",
+        &[],
+    ).await?;
+    let exists: bool = exists_row.get(0);
+    if !exists {
+        return Ok(None);
+    }
+    let version_row = db_conn.query_one("SELECT schema_version FROM bimdb.schema_version", &[]).await?;
+    Ok(Some(version_row.get(0)))
+}
+
+/// Checks whether the database schema has the version expected by this binary.
+pub(crate) async fn check_schema_version(db_conn: &Client) -> Result<(), String> {
+    let version_opt = schema_version(db_conn).await
+        .map_err(|e| format!("failed to obtain database schema version: {}", e))?;
+    match version_opt {
+        Some(v) if v == SCHEMA_VERSION => Ok(()),
+        Some(v) if v > SCHEMA_VERSION => Err(format!(
+            "database schema version {} is newer than version {} expected by this binary",
+            v, SCHEMA_VERSION,
+        )),
+        Some(v) => Err(format!(
+            "database schema is at version {} but version {} is expected; run `bimdatabase migrate` first",
+            v, SCHEMA_VERSION,
+        )),
+        None => Err("database schema has not been set up; run `bimdatabase migrate` first".to_owned()),
+    }
+}
+
+/// Obtains the migration lock and returns the schema version of the database as seen within the
+/// transaction.
+async fn lock_schema_version(db_txn: &Transaction<'_>) -> Result<Option<i64>, tokio_postgres::Error> {
+    db_txn.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY]).await?;
+    schema_version(db_txn.client()).await
+}
+
+/// Sets up the schema if the database is empty and applies all pending migrations, each in its own
+/// transaction.
+pub(crate) async fn migrate(db_conn: &mut Client) -> Result<(), String> {
+    let initial_version = schema_version(db_conn).await
+        .map_err(|e| format!("failed to obtain schema version: {}", e))?;
+    match initial_version {
+        Some(v) => info!("database schema is at version {}; this binary expects version {}", v, SCHEMA_VERSION),
+        None => info!("database schema has not been set up; this binary expects version {}", SCHEMA_VERSION),
+    }
+
+    loop {
+        let db_txn = db_conn.transaction().await
+            .map_err(|e| format!("failed to begin transaction: {}", e))?;
+        let version_opt = lock_schema_version(&db_txn).await
+            .map_err(|e| format!("failed to obtain schema version: {}", e))?;
+
+        let (sql, description) = match version_opt {
+            None => (SCHEMA, format!("set up schema at version {}", SCHEMA_VERSION)),
+            Some(v) if v == SCHEMA_VERSION => {
+                info!("database schema is up to date at version {}", v);
+                return Ok(());
+            },
+            Some(v) if v > SCHEMA_VERSION => {
+                return Err(format!(
+                    "database schema version {} is newer than version {} expected by this binary",
+                    v, SCHEMA_VERSION,
+                ));
+            },
+            Some(v) if v < 1 => return Err(format!("invalid database schema version {}", v)),
+            Some(v) => (MIGRATIONS[(v - 1) as usize], format!("migrated schema from version {} to {}", v, v + 1)),
+        };
+
+        db_txn.batch_execute(sql).await
+            .map_err(|e| format!("failed to apply migration: {}", e))?;
+
+        // make sure the script updated the version, otherwise we would loop forever
+        let new_version = lock_schema_version(&db_txn).await
+            .map_err(|e| format!("failed to obtain schema version: {}", e))?;
+        let expected_version = version_opt.map(|v| v + 1).unwrap_or(SCHEMA_VERSION);
+        if new_version != Some(expected_version) {
+            return Err(format!(
+                "schema version is {:?} instead of {} after migration; rolling back",
+                new_version, expected_version,
+            ));
+        }
+
+        // carry over the value sets from the configuration file once, when their tables are created
+        if version_opt.is_none() || expected_version == VALUE_SETS_SCHEMA_VERSION {
+            let (veh_classes, power_sources) = value_sets::import_config_value_sets(&db_txn, &config::current().value_sets).await
+                .map_err(|e| format!("failed to import value sets from configuration: {}", e))?;
+            info!(
+                "imported {} vehicle classes and {} power sources from the configuration",
+                veh_classes, power_sources,
+            );
+        }
+
+        db_txn.commit().await
+            .map_err(|e| format!("failed to commit migration: {}", e))?;
+        info!("{}", description);
+    }
+}
@@ -0,0 +1,100 @@
+use std::cmp::Ordering;
+
+
+/// Returns the key by which vehicle numbers are ordered naturally. Equivalent to the database
+/// function `bimdb.natural_sort_key`.
+///
+/// Runs of ASCII digits are replaced by their value without leading zeroes, prefixed with the length
+/// of that value as three digits. Comparing the keys bytewise thereby compares the digit runs
+/// numerically, e.g. "E1 450" sorts before "E1 4500" and "9" before "10".
+///
+/// The length prefix limits digit runs to 999 significant digits; beyond that, the database function
+/// truncates the prefix and both orderings diverge. Vehicle numbers are limited to 256 characters by
+/// [`ExportedVehicle::validate`](crate::ExportedVehicle::validate), so this limit is never reached.
+pub(crate) fn natural_sort_key(number: &str) -> String {
+    let mut key = String::with_capacity(number.len() + 8);
+    let mut rest = number;
+    while let Some(first) = rest.chars().next() {
+        let is_digit_run = first.is_ascii_digit();
+        let run_end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit_run)
+            .unwrap_or(rest.len());
+        let (run, remainder) = rest.split_at(run_end);
+        if is_digit_run {
+            let digits = run.trim_start_matches('0');
+            let digits = if digits.is_empty() { "0" } else { digits };
+            key.push_str(&format!("{:03}", digits.len()));
+            key.push_str(digits);
+        } else {
+            key.push_str(run);
+        }
+        rest = remainder;
+    }
+    key
+}
+
+/// Compares two vehicle numbers naturally, falling back to comparing them bytewise if their keys are
+/// equal (e.g. "7" and "007").
+pub(crate) fn compare_vehicle_numbers(left: &str, right: &str) -> Ordering {
+    natural_sort_key(left).cmp(&natural_sort_key(right))
+        .then_with(|| left.cmp(right))
+}
+
+/// Returns the SQL expressions ordering the given vehicle number column the same way as
+/// [`compare_vehicle_numbers`].
+pub(crate) fn sql_order_keys(column: &str) -> [String; 2] {
+    [
+        format!("bimdb.natural_sort_key({}) COLLATE \"C\"", column),
+        format!("{} COLLATE \"C\"", column),
+    ]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_vehicle_numbers, natural_sort_key};
+    use std::cmp::Ordering;
+
+    fn sorted(numbers: &[&str]) -> Vec<String> {
+        let mut sorted: Vec<String> = numbers.iter().map(|n| (*n).to_owned()).collect();
+        sorted.sort_by(|l, r| compare_vehicle_numbers(l, r));
+        sorted
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(natural_sort_key(""), "");
+        assert_eq!(natural_sort_key("9"), "0019");
+        assert_eq!(natural_sort_key("10"), "00210");
+        assert_eq!(natural_sort_key("E1 4500"), "E0011 0044500");
+        assert_eq!(natural_sort_key("c3 1234a"), "c0013 0041234a");
+        assert_eq!(natural_sort_key("0"), "0010");
+        assert_eq!(natural_sort_key("000"), "0010");
+    }
+
+    #[test]
+    fn test_numeric_runs() {
+        assert_eq!(sorted(&["100", "10", "9"]), ["9", "10", "100"]);
+        assert_eq!(compare_vehicle_numbers("9", "10"), Ordering::Less);
+        assert_eq!(compare_vehicle_numbers("100", "10"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_leading_zeroes() {
+        assert_eq!(natural_sort_key("007"), natural_sort_key("7"));
+        assert_eq!(sorted(&["7", "010", "007", "8"]), ["007", "7", "8", "010"]);
+        assert_eq!(compare_vehicle_numbers("007", "007"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_prefixes_and_suffixes() {
+        assert_eq!(
+            sorted(&["E1 4500", "E1 450", "E10 1", "E2 9", "E1 45"]),
+            ["E1 45", "E1 450", "E1 4500", "E2 9", "E10 1"],
+        );
+        assert_eq!(
+            sorted(&["c3 1234a", "c3 1234", "c3 999b", "c3 1234b", "c12 1"]),
+            ["c3 999b", "c3 1234", "c3 1234a", "c3 1234b", "c12 1"],
+        );
+    }
+}
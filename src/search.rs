@@ -0,0 +1,530 @@
+use std::collections::BTreeSet;
+
+use crate::natural_sort;
+
+
+/// Query parameters by which vehicles can be filtered, the columns they compare and their labels.
+const FILTER_COLUMNS: [(&str, &str, &str); 8] = [
+    ("company", "company", "Company"),
+    ("veh-number", "veh_number", "Number"),
+    ("type-code", "type_code", "Type"),
+    ("veh-class", "veh_class", "Class"),
+    ("in-service-since", "in_service_since", "In Service Since"),
+    ("out-of-service-since", "out_of_service_since", "Out of Service Since"),
+    ("manufacturer", "manufacturer", "Manufacturer"),
+    ("depot", "depot", "Depot"),
+];
+
+
+/// How a column is compared to a filter value.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum MatchMode {
+    #[default] Exact,
+    Prefix,
+    Substring,
+    Null,
+    NotNull,
+}
+impl MatchMode {
+    pub const ALL: [MatchMode; 5] = [Self::Exact, Self::Prefix, Self::Substring, Self::Null, Self::NotNull];
+
+    pub fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "exact" => Some(Self::Exact),
+            "prefix" => Some(Self::Prefix),
+            "substring" => Some(Self::Substring),
+            "null" => Some(Self::Null),
+            "not-null" => Some(Self::NotNull),
+            _ => None,
+        }
+    }
+
+    pub fn as_param(&self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Prefix => "prefix",
+            Self::Substring => "substring",
+            Self::Null => "null",
+            Self::NotNull => "not-null",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Exact => "is",
+            Self::Prefix => "starts with",
+            Self::Substring => "contains",
+            Self::Null => "is empty",
+            Self::NotNull => "is not empty",
+        }
+    }
+
+    /// Whether this mode compares the column to a value.
+    pub fn takes_value(&self) -> bool {
+        !matches!(self, Self::Null | Self::NotNull)
+    }
+}
+
+/// A filter on a single column of the vehicle table.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct ColumnFilter {
+    pub param: &'static str,
+    pub column: &'static str,
+    pub label: &'static str,
+    pub mode: MatchMode,
+    pub value: String,
+}
+impl ColumnFilter {
+    pub fn is_active(&self) -> bool {
+        !self.mode.takes_value() || !self.value.is_empty()
+    }
+
+    pub fn mode_param(&self) -> String {
+        format!("{}-mode", self.param)
+    }
+}
+
+/// Filters on the vehicle table as passed in the query string.
+///
+/// `columns` contains an entry for each filterable column, whether it is active or not.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct VehicleFilter {
+    pub columns: Vec<ColumnFilter>,
+    pub power_sources: BTreeSet<String>,
+    pub other_data_key: String,
+    pub other_data_value: String,
+}
+impl VehicleFilter {
+    /// Reads the filter from the query parameters.
+    ///
+    /// Returns a human-readable description of the problem if a parameter is invalid.
+    pub fn from_query(query_pairs: &[(String, Option<String>)]) -> Result<Self, String> {
+        let last_value = |key: &str| -> String {
+            query_pairs.iter()
+                .filter(|(k, _v)| k == key)
+                .flat_map(|(_k, v)| v)
+                .last()
+                .map(|v| v.trim().to_owned())
+                .unwrap_or_default()
+        };
+
+        let mut columns = Vec::with_capacity(FILTER_COLUMNS.len());
+        for (param, column, label) in FILTER_COLUMNS {
+            let mode_param = format!("{}-mode", param);
+            let mode_str = last_value(&mode_param);
+            let mode = if mode_str.is_empty() {
+                MatchMode::default()
+            } else {
+                match MatchMode::from_param(&mode_str) {
+                    Some(m) => m,
+                    None => return Err(format!("invalid value for '{}'", mode_param)),
+                }
+            };
+            columns.push(ColumnFilter {
+                param,
+                column,
+                label,
+                mode,
+                value: last_value(param),
+            });
+        }
+
+        let power_sources = query_pairs.iter()
+            .filter(|(k, _v)| k == "power-source")
+            .filter_map(|(_k, v)| v.as_ref())
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_owned())
+            .collect();
+
+        let other_data_key = last_value("other-data-key");
+        let other_data_value = last_value("other-data-value");
+        if other_data_key.is_empty() && !other_data_value.is_empty() {
+            return Err("'other-data-value' requires 'other-data-key'".to_owned());
+        }
+
+        Ok(Self {
+            columns,
+            power_sources,
+            other_data_key,
+            other_data_value,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.columns.iter().any(|c| c.is_active())
+            || !self.power_sources.is_empty()
+            || !self.other_data_key.is_empty()
+    }
+
+    /// Returns an SQL condition implementing this filter, referring to the vehicle as `b`.
+    ///
+    /// The values to compare are appended to `params` and referenced by their position in it.
+    pub fn sql_condition(&self, params: &mut Vec<String>) -> String {
+        let mut conditions = Vec::new();
+        for column_filter in &self.columns {
+            if !column_filter.is_active() {
+                continue;
+            }
+            let column = column_filter.column;
+            match column_filter.mode {
+                MatchMode::Null => conditions.push(format!("b.{} IS NULL", column)),
+                MatchMode::NotNull => conditions.push(format!("b.{} IS NOT NULL", column)),
+                MatchMode::Exact => {
+                    params.push(column_filter.value.clone());
+                    conditions.push(format!("b.{} = ${}", column, params.len()));
+                },
+                MatchMode::Prefix => {
+                    params.push(format!("{}%", escape_like(&column_filter.value)));
+                    conditions.push(format!("b.{} LIKE ${}", column, params.len()));
+                },
+                MatchMode::Substring => {
+                    params.push(format!("%{}%", escape_like(&column_filter.value)));
+                    conditions.push(format!("b.{} LIKE ${}", column, params.len()));
+                },
+            }
+        }
+
+        for power_source in &self.power_sources {
+            params.push(power_source.clone());
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM bimdb.power_sources ps WHERE ps.bim_id = b.id AND ps.power_source = ${})",
+                params.len(),
+            ));
+        }
+
+        if !self.other_data_key.is_empty() {
+            params.push(self.other_data_key.clone());
+            let key_index = params.len();
+            if !self.other_data_value.is_empty() {
+                params.push(self.other_data_value.clone());
+                conditions.push(format!("b.other_data ->> ${} = ${}", key_index, params.len()));
+            } else {
+                conditions.push(format!("b.other_data ? ${}", key_index));
+            }
+        }
+
+        if !conditions.is_empty() {
+            conditions.join(" AND ")
+        } else {
+            "TRUE".to_owned()
+        }
+    }
+
+    /// Returns the active filter parameters as a query string, each parameter preceded by `&`.
+    pub fn to_query_string(&self) -> String {
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for column_filter in &self.columns {
+            if !column_filter.is_active() {
+                continue;
+            }
+            if column_filter.mode.takes_value() {
+                serializer.append_pair(column_filter.param, &column_filter.value);
+            }
+            if column_filter.mode != MatchMode::default() {
+                serializer.append_pair(&column_filter.mode_param(), column_filter.mode.as_param());
+            }
+        }
+        for power_source in &self.power_sources {
+            serializer.append_pair("power-source", power_source);
+        }
+        if !self.other_data_key.is_empty() {
+            serializer.append_pair("other-data-key", &self.other_data_key);
+            if !self.other_data_value.is_empty() {
+                serializer.append_pair("other-data-value", &self.other_data_value);
+            }
+        }
+        let query = serializer.finish();
+        if !query.is_empty() {
+            format!("&{}", query)
+        } else {
+            query
+        }
+    }
+}
+
+/// The order of the vehicle list as passed in the query string.
+///
+/// `key` is the filter parameter name of a column. Vehicle numbers are sorted naturally.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct VehicleSort {
+    pub key: &'static str,
+    pub descending: bool,
+}
+impl Default for VehicleSort {
+    fn default() -> Self {
+        Self {
+            key: FILTER_COLUMNS[0].0,
+            descending: false,
+        }
+    }
+}
+impl VehicleSort {
+    /// Reads the sort order from the query parameters.
+    ///
+    /// Returns a human-readable description of the problem if a parameter is invalid.
+    pub fn from_query(query_pairs: &[(String, Option<String>)]) -> Result<Self, String> {
+        let last_value = |key: &str| -> Option<&str> {
+            query_pairs.iter()
+                .filter(|(k, _v)| k == key)
+                .filter_map(|(_k, v)| v.as_ref().map(|v2| v2.as_str().trim()))
+                .next_back()
+                .filter(|v| !v.is_empty())
+        };
+
+        let mut sort = Self::default();
+        if let Some(key) = last_value("sort") {
+            sort.key = match Self::known_key(key) {
+                Some(k) => k,
+                None => return Err("invalid value for 'sort'".to_owned()),
+            };
+        }
+        match last_value("dir") {
+            None|Some("asc") => {},
+            Some("desc") => sort.descending = true,
+            Some(_) => return Err("invalid value for 'dir'".to_owned()),
+        }
+        Ok(sort)
+    }
+
+    fn known_key(key: &str) -> Option<&'static str> {
+        FILTER_COLUMNS.iter()
+            .map(|(param, _column, _label)| *param)
+            .find(|param| *param == key)
+    }
+
+    /// Returns the contents of an SQL `ORDER BY` clause implementing this order, referring to the
+    /// vehicle as `b`.
+    pub fn sql_order_by(&self) -> String {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        let column = FILTER_COLUMNS.iter()
+            .find(|(param, _column, _label)| *param == self.key)
+            .map(|(_param, column, _label)| *column)
+            .expect("sort key is a filter column");
+        let number_order = natural_sort::sql_order_keys("b.veh_number");
+        let mut order = Vec::new();
+        if column == "veh_number" {
+            for number_key in &number_order {
+                order.push(format!("{} {}", number_key, direction));
+            }
+        } else {
+            order.push(format!("b.{} {} NULLS LAST", column, direction));
+        }
+
+        // make the order stable
+        let tie_breakers = ["b.company".to_owned(), number_order[0].clone(), number_order[1].clone(), "b.id".to_owned()];
+        for tie_breaker in tie_breakers {
+            if !order.iter().any(|o| o.starts_with(&format!("{} ", tie_breaker))) {
+                order.push(tie_breaker);
+            }
+        }
+        order.join(", ")
+    }
+
+    /// Returns the sort parameters as a query string, each parameter preceded by `&`. The default
+    /// order is represented by an empty string.
+    pub fn to_query_string(self) -> String {
+        if self == Self::default() {
+            return String::new();
+        }
+        let mut query = format!("&sort={}", self.key);
+        if self.descending {
+            query.push_str("&dir=desc");
+        }
+        query
+    }
+
+    /// Returns the order obtained by choosing the given key: ascending, unless the list is already
+    /// sorted ascending by that key.
+    pub fn toggled(&self, key: &'static str) -> Self {
+        Self {
+            key,
+            descending: self.key == key && !self.descending,
+        }
+    }
+
+    /// Returns an arrow if the list is sorted by the given key.
+    pub fn indicator(&self, key: &str) -> &'static str {
+        if self.key != key {
+            ""
+        } else if self.descending {
+            "\u{25BC}"
+        } else {
+            "\u{25B2}"
+        }
+    }
+}
+
+
+/// Escapes the wildcard characters of a `LIKE` pattern.
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> Vec<(String, Option<String>)> {
+        pairs.iter()
+            .map(|(k, v)| ((*k).to_owned(), Some((*v).to_owned())))
+            .collect()
+    }
+
+    fn filter(pairs: &[(&str, &str)]) -> VehicleFilter {
+        VehicleFilter::from_query(&query(pairs))
+            .expect("valid filter")
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("abc"), "abc");
+        assert_eq!(escape_like("50%"), "50\\%");
+        assert_eq!(escape_like("a_b"), "a\\_b");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+        assert_eq!(escape_like("%_\\"), "\\%\\_\\\\");
+    }
+
+    #[test]
+    fn test_inactive_filter() {
+        let vehicle_filter = filter(&[("veh-number", ""), ("type-code", "  ")]);
+        assert!(!vehicle_filter.is_active());
+        let mut params = Vec::new();
+        assert_eq!(vehicle_filter.sql_condition(&mut params), "TRUE");
+        assert!(params.is_empty());
+        assert_eq!(vehicle_filter.to_query_string(), "");
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let vehicle_filter = filter(&[
+            ("company", "wl"),
+            ("veh-number", "4_"), ("veh-number-mode", "prefix"),
+            ("type-code", "100%"), ("type-code-mode", "substring"),
+            ("manufacturer-mode", "null"),
+            ("depot-mode", "not-null"),
+        ]);
+        let mut params = Vec::new();
+        assert_eq!(
+            vehicle_filter.sql_condition(&mut params),
+            "b.company = $1 AND b.veh_number LIKE $2 AND b.type_code LIKE $3 \
+                AND b.manufacturer IS NULL AND b.depot IS NOT NULL",
+        );
+        assert_eq!(params, ["wl", "4\\_%", "%100\\%%"]);
+    }
+
+    #[test]
+    fn test_null_modes_ignore_value() {
+        let vehicle_filter = filter(&[("depot", "Rudolfsheim"), ("depot-mode", "null")]);
+        let mut params = Vec::new();
+        assert_eq!(vehicle_filter.sql_condition(&mut params), "b.depot IS NULL");
+        assert!(params.is_empty());
+        assert_eq!(vehicle_filter.to_query_string(), "&depot-mode=null");
+    }
+
+    #[test]
+    fn test_invalid_mode() {
+        let err = VehicleFilter::from_query(&query(&[("depot-mode", "fuzzy")]))
+            .unwrap_err();
+        assert_eq!(err, "invalid value for 'depot-mode'");
+    }
+
+    #[test]
+    fn test_other_data() {
+        let mut params = Vec::new();
+        assert_eq!(
+            filter(&[("other-data-key", "livery")]).sql_condition(&mut params),
+            "b.other_data ? $1",
+        );
+        assert_eq!(params, ["livery"]);
+
+        let mut params = Vec::new();
+        assert_eq!(
+            filter(&[("other-data-key", "livery"), ("other-data-value", "red")]).sql_condition(&mut params),
+            "b.other_data ->> $1 = $2",
+        );
+        assert_eq!(params, ["livery", "red"]);
+
+        let err = VehicleFilter::from_query(&query(&[("other-data-value", "red")]))
+            .unwrap_err();
+        assert_eq!(err, "'other-data-value' requires 'other-data-key'");
+    }
+
+    #[test]
+    fn test_parameter_numbering() {
+        let vehicle_filter = filter(&[
+            ("veh-class", "tram"),
+            ("power-source", "overhead-line"),
+            ("power-source", "battery"),
+            ("other-data-key", "livery"),
+            ("other-data-value", "red"),
+        ]);
+
+        // the filter continues the numbering of parameters added before it
+        let mut params = vec!["graz".to_owned()];
+        assert_eq!(
+            vehicle_filter.sql_condition(&mut params),
+            "b.veh_class = $2 \
+                AND EXISTS (SELECT 1 FROM bimdb.power_sources ps WHERE ps.bim_id = b.id AND ps.power_source = $3) \
+                AND EXISTS (SELECT 1 FROM bimdb.power_sources ps WHERE ps.bim_id = b.id AND ps.power_source = $4) \
+                AND b.other_data ->> $5 = $6",
+        );
+        assert_eq!(params, ["graz", "tram", "battery", "overhead-line", "livery", "red"]);
+    }
+
+    #[test]
+    fn test_query_string_round_trip() {
+        let vehicle_filter = filter(&[
+            ("company", "Wiener Linien"),
+            ("veh-number", "4&5=6"), ("veh-number-mode", "prefix"),
+            ("type-code", "100%"), ("type-code-mode", "substring"),
+            ("manufacturer-mode", "not-null"),
+            ("power-source", "overhead-line"),
+            ("other-data-key", "livery"),
+            ("other-data-value", "red+white"),
+        ]);
+        let query_string = vehicle_filter.to_query_string();
+        assert!(query_string.starts_with('&'));
+        let parsed_pairs = crate::get_query_pairs(Some(&query_string[1..]))
+            .expect("valid query string");
+        assert_eq!(VehicleFilter::from_query(&parsed_pairs), Ok(vehicle_filter));
+    }
+
+    #[test]
+    fn test_sort() {
+        let sort = VehicleSort::from_query(&query(&[])).unwrap();
+        assert_eq!(sort, VehicleSort::default());
+        assert_eq!(sort.to_query_string(), "");
+        assert_eq!(
+            sort.sql_order_by(),
+            "b.company ASC NULLS LAST, bimdb.natural_sort_key(b.veh_number) COLLATE \"C\", \
+                b.veh_number COLLATE \"C\", b.id",
+        );
+
+        let sort = VehicleSort::from_query(&query(&[("sort", "veh-number"), ("dir", "desc")])).unwrap();
+        assert_eq!(sort.to_query_string(), "&sort=veh-number&dir=desc");
+        assert_eq!(
+            sort.sql_order_by(),
+            "bimdb.natural_sort_key(b.veh_number) COLLATE \"C\" DESC, b.veh_number COLLATE \"C\" DESC, \
+                b.company, b.id",
+        );
+
+        let sort = VehicleSort::from_query(&query(&[("sort", "depot")])).unwrap();
+        assert_eq!(
+            sort.sql_order_by(),
+            "b.depot ASC NULLS LAST, b.company, bimdb.natural_sort_key(b.veh_number) COLLATE \"C\", \
+                b.veh_number COLLATE \"C\", b.id",
+        );
+
+        assert!(VehicleSort::from_query(&query(&[("sort", "other_data")])).is_err());
+        assert!(VehicleSort::from_query(&query(&[("dir", "up")])).is_err());
+    }
+}
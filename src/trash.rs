@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use askama::Template;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, Response};
+use hyper::body::{Bytes, Incoming};
+use tokio_postgres::error::SqlState;
+use tracing::{error, info, instrument};
+
+use crate::{
+    db_connect, return_400, return_403, return_405, return_409, return_500, set_change_context,
+};
+use crate::auth;
+use crate::config;
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct TrashedVehiclePart {
+    pub id: i64,
+    pub company: String,
+    pub veh_number: String,
+    pub type_code: String,
+    pub veh_class: String,
+    pub deleted_at: String,
+    pub deleted_by: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "trash.html")]
+struct TrashTemplate {
+    pub base_path: String,
+    pub vehicles: Vec<TrashedVehiclePart>,
+}
+
+
+fn redirect_to(page: &str) -> Response<Full<Bytes>> {
+    let base_path = &config::current()
+        .http.base_path;
+    Response::builder()
+        .status(302)
+        .header("Location", format!("{}/{}", base_path, page))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("redirecting...")))
+        .unwrap_or_else(|_| return_500())
+}
+
+/// Handles the trash, which lists deleted vehicles and allows administrators of their company to
+/// restore or permanently purge them.
+#[instrument(skip_all)]
+pub(crate) async fn handle_trash(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    if !principal.may_administer_any() {
+        return return_403("you may not administer the trash");
+    }
+
+    let mut db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+
+    if request.method() == Method::GET {
+        let vehicle_rows_res = db_conn.query(
+            "
+                SELECT
+                    b.id, b.company, b.veh_number, b.type_code, b.veh_class,
+                    to_char(b.deleted_at, 'YYYY-MM-DD HH24:MI:SS TZ'), b.deleted_by
+                FROM
+                    bimdb.bims b
+                WHERE
+                    b.deleted_at IS NOT NULL
+                ORDER BY
+                    b.deleted_at DESC, b.id DESC
+            ",
+            &[],
+        ).await;
+        let vehicle_rows = match vehicle_rows_res {
+            Ok(vr) => vr,
+            Err(e) => {
+                error!("failed to obtain deleted vehicles: {}", e);
+                return return_500();
+            },
+        };
+        let vehicles = vehicle_rows.into_iter()
+            .map(|row| TrashedVehiclePart {
+                id: row.get(0),
+                company: row.get(1),
+                veh_number: row.get(2),
+                type_code: row.get(3),
+                veh_class: row.get(4),
+                deleted_at: row.get(5),
+                deleted_by: row.get(6),
+            })
+            .filter(|v| principal.may_administer(&v.company))
+            .collect();
+
+        let template = TrashTemplate {
+            base_path: config::current().http.base_path.clone(),
+            vehicles,
+        };
+        let template_text = template.render()
+            .expect("failed to render template");
+        return Response::builder()
+            .status(200)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(template_text)))
+            .unwrap_or_else(|_| return_500());
+    } else if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::GET, Method::POST]);
+    }
+
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return return_500();
+        },
+    };
+    let form_values: HashMap<Cow<str>, Cow<str>> = form_urlencoded::parse(&request_bytes)
+        .collect();
+    let id: i64 = match form_values.get("id").map(|i| i.parse()) {
+        Some(Ok(i)) => i,
+        Some(Err(_)) => return return_400("invalid value for field 'id'"),
+        None => return return_400("missing field 'id'"),
+    };
+    let purge = match form_values.get("action").map(|a| a.as_ref()) {
+        Some("restore") => false,
+        Some("purge") => true,
+        _ => return return_400("invalid value for field 'action'"),
+    };
+
+    let db_txn = match db_conn.transaction().await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to begin database transaction: {}", e);
+            return return_500();
+        },
+    };
+    if let Err(e) = set_change_context(&db_txn, Some(&principal), Some(remote_addr)).await {
+        error!("failed to set change context: {}", e);
+        return return_500();
+    }
+
+    let company_row_res = db_txn.query_opt(
+        "SELECT company, veh_number FROM bimdb.bims WHERE id = $1 AND deleted_at IS NOT NULL FOR UPDATE",
+        &[&id],
+    ).await;
+    let (company, veh_number): (String, String) = match company_row_res {
+        Ok(Some(row)) => (row.get(0), row.get(1)),
+        Ok(None) => return return_400("failed to find this vehicle in the trash"),
+        Err(e) => {
+            error!("failed to obtain company of deleted vehicle {}: {}", id, e);
+            return return_500();
+        },
+    };
+    if !principal.may_administer(&company) {
+        return return_403(&format!("you may not administer the trash of company {:?}", company));
+    }
+
+    if purge {
+        // power sources are deleted by cascade; vehicles in the trash are never coupled
+        if let Err(e) = db_txn.execute("DELETE FROM bimdb.bims WHERE id = $1", &[&id]).await {
+            error!("failed to purge vehicle {}: {}", id, e);
+            return return_500();
+        }
+    } else {
+        let restore_res = db_txn.execute(
+            "UPDATE bimdb.bims SET deleted_at = NULL, deleted_by = NULL WHERE id = $1",
+            &[&id],
+        ).await;
+        if let Err(e) = restore_res {
+            if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                return return_409("another vehicle of this company now has this number");
+            }
+            error!("failed to restore vehicle {}: {}", id, e);
+            return return_500();
+        }
+    }
+
+    if let Err(e) = db_txn.commit().await {
+        error!("failed to commit trash transaction: {}", e);
+        return return_500();
+    }
+    info!(
+        "{} ({:?}) {} vehicle {:?} of company {:?}",
+        remote_addr, principal.username, if purge { "purged" } else { "restored" }, veh_number, company,
+    );
+
+    redirect_to("trash")
+}
@@ -0,0 +1,465 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+
+use askama::Template;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, Response};
+use hyper::body::{Bytes, Incoming};
+use tokio_postgres::error::SqlState;
+use tracing::{error, info, instrument};
+
+use crate::{db_connect, return_400, return_403, return_405, return_409, return_500};
+use crate::auth::{
+    self, generate_token, hash_password, hash_token, Principal, Role, ALL_COMPANIES, MAX_PASSWORD_LENGTH,
+};
+use crate::config;
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct UserPart {
+    pub id: i64,
+    pub username: String,
+    pub roles: BTreeMap<String, Role>,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct TokenPart {
+    pub id: i64,
+    pub username: String,
+    pub description: String,
+    pub read_write: bool,
+    pub companies: Option<Vec<String>>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+struct UsersTemplate {
+    pub base_path: String,
+    pub principal: Principal,
+    pub users: Vec<UserPart>,
+    pub all_roles: [Role; 3],
+}
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+struct TokensTemplate {
+    pub base_path: String,
+    pub tokens: Vec<TokenPart>,
+    pub usernames: Vec<String>,
+    pub new_token: Option<String>,
+}
+
+
+fn redirect_to(page: &str) -> Response<Full<Bytes>> {
+    let base_path = &config::current()
+        .http.base_path;
+    Response::builder()
+        .status(302)
+        .header("Location", format!("{}/{}", base_path, page))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("redirecting...")))
+        .unwrap_or_else(|_| return_500())
+}
+
+async fn load_users(db_conn: &tokio_postgres::Client) -> Option<Vec<UserPart>> {
+    let rows_res = db_conn.query(
+        "
+            SELECT
+                u.id, u.username, ur.company, ur.role
+            FROM
+                bimdb.users u
+                LEFT OUTER JOIN bimdb.user_roles ur ON ur.user_id = u.id
+            ORDER BY
+                u.username, u.id
+        ",
+        &[],
+    ).await;
+    let rows = match rows_res {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to obtain users: {}", e);
+            return None;
+        },
+    };
+
+    let mut users: Vec<UserPart> = Vec::new();
+    for row in rows {
+        let id: i64 = row.get(0);
+        if users.last().map(|u| u.id != id).unwrap_or(true) {
+            users.push(UserPart {
+                id,
+                username: row.get(1),
+                roles: BTreeMap::new(),
+            });
+        }
+        let company: Option<String> = row.get(2);
+        let role_str: Option<String> = row.get(3);
+        if let (Some(company), Some(role_str)) = (company, role_str) {
+            if let Some(role) = Role::from_db(&role_str) {
+                users.last_mut().expect("user was just pushed")
+                    .roles.insert(company, role);
+            }
+        }
+    }
+    Some(users)
+}
+
+async fn find_user_id(db_conn: &tokio_postgres::Client, username: &str) -> Result<Option<i64>, tokio_postgres::Error> {
+    let row_opt = db_conn.query_opt(
+        "SELECT id FROM bimdb.users WHERE username = $1",
+        &[&username],
+    ).await?;
+    Ok(row_opt.map(|row| row.get(0)))
+}
+
+/// Handles the user administration page.
+///
+/// Administrators of specific companies may assign roles for those companies; creating and deleting
+/// users requires administrator rights for all companies.
+#[instrument(skip_all)]
+pub(crate) async fn handle_users(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    if !principal.may_administer_any() {
+        return return_403("you may not administer users");
+    }
+
+    let db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+
+    if request.method() == Method::GET {
+        let users = match load_users(&db_conn).await {
+            Some(u) => u,
+            None => return return_500(),
+        };
+        let template = UsersTemplate {
+            base_path: config::current().http.base_path.clone(),
+            principal,
+            users,
+            all_roles: Role::ALL,
+        };
+        let template_text = template.render()
+            .expect("failed to render template");
+        return Response::builder()
+            .status(200)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(template_text)))
+            .unwrap_or_else(|_| return_500());
+    } else if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::GET, Method::POST]);
+    }
+
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return return_500();
+        },
+    };
+    let form_values: HashMap<Cow<str>, Cow<str>> = form_urlencoded::parse(&request_bytes)
+        .collect();
+    let username = form_values.get("username")
+        .map(|u| u.trim())
+        .unwrap_or("");
+    if username.is_empty() {
+        return return_400("field 'username' must not be empty");
+    }
+
+    match form_values.get("action").map(|a| a.as_ref()) {
+        Some("create-user") => {
+            if !principal.may_administer(ALL_COMPANIES) {
+                return return_403("only administrators of all companies may create users");
+            }
+            let password = form_values.get("password")
+                .map(|p| p.as_ref())
+                .unwrap_or("");
+            if password.is_empty() {
+                return return_400("field 'password' must not be empty");
+            }
+            if username.len() > 256 {
+                return return_400("field 'username' must be at most 256 bytes long");
+            }
+            if password.len() > MAX_PASSWORD_LENGTH {
+                return return_400(&format!("field 'password' must be at most {} bytes long", MAX_PASSWORD_LENGTH));
+            }
+            let password_hash = match hash_password(password).await {
+                Ok(ph) => ph,
+                Err(e) => {
+                    error!("failed to hash password: {}", e);
+                    return return_500();
+                },
+            };
+            let insert_res = db_conn.execute(
+                "INSERT INTO bimdb.users (id, username, password_hash) VALUES (DEFAULT, $1, $2)",
+                &[&username, &password_hash],
+            ).await;
+            if let Err(e) = insert_res {
+                if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    return return_409("a user with this name already exists");
+                }
+                error!("failed to create user {:?}: {}", username, e);
+                return return_500();
+            }
+            info!("{} ({:?}) created user {:?}", remote_addr, principal.username, username);
+        },
+        Some("set-role") => {
+            let company = form_values.get("company")
+                .map(|c| c.trim())
+                .unwrap_or("");
+            if company.is_empty() {
+                return return_400("field 'company' must not be empty");
+            }
+            if company.chars().count() > 256 {
+                return return_400("field 'company' must not be longer than 256 characters");
+            }
+            let role_opt = match form_values.get("role").map(|r| r.as_ref()) {
+                None|Some("") => None,
+                Some(r) => match Role::from_db(r) {
+                    Some(role) => Some(role),
+                    None => return return_400("invalid value for field 'role'"),
+                },
+            };
+            if !principal.may_administer(company) {
+                return return_403(&format!("you may not assign roles for company {:?}", company));
+            }
+            let user_id = match find_user_id(&db_conn, username).await {
+                Ok(Some(ui)) => ui,
+                Ok(None) => return return_400("failed to find this user"),
+                Err(e) => {
+                    error!("failed to find user {:?}: {}", username, e);
+                    return return_500();
+                },
+            };
+            let store_res = if let Some(role) = role_opt {
+                db_conn.execute(
+                    "
+                        INSERT INTO bimdb.user_roles (user_id, company, role) VALUES ($1, $2, $3)
+                        ON CONFLICT (user_id, company) DO UPDATE SET role = EXCLUDED.role
+                    ",
+                    &[&user_id, &company, &role.as_db()],
+                ).await
+            } else {
+                db_conn.execute(
+                    "DELETE FROM bimdb.user_roles WHERE user_id = $1 AND company = $2",
+                    &[&user_id, &company],
+                ).await
+            };
+            if let Err(e) = store_res {
+                error!("failed to set role of user {:?} for company {:?}: {}", username, company, e);
+                return return_500();
+            }
+            info!(
+                "{} ({:?}) set role of user {:?} for company {:?} to {:?}",
+                remote_addr, principal.username, username, company, role_opt,
+            );
+        },
+        Some("delete-user") => {
+            if !principal.may_administer(ALL_COMPANIES) {
+                return return_403("only administrators of all companies may delete users");
+            }
+            if username == principal.username {
+                return return_400("you may not delete yourself");
+            }
+            let affected_rows = match db_conn.execute("DELETE FROM bimdb.users WHERE username = $1", &[&username]).await {
+                Ok(ar) => ar,
+                Err(e) => {
+                    error!("failed to delete user {:?}: {}", username, e);
+                    return return_500();
+                },
+            };
+            if affected_rows == 0 {
+                return return_400("failed to find this user");
+            }
+            info!("{} ({:?}) deleted user {:?}", remote_addr, principal.username, username);
+        },
+        _ => return return_400("invalid value for field 'action'"),
+    }
+
+    redirect_to("users")
+}
+
+async fn render_tokens(db_conn: &tokio_postgres::Client, new_token: Option<String>) -> Response<Full<Bytes>> {
+    let token_rows_res = db_conn.query(
+        "
+            SELECT
+                t.id, u.username, t.description, t.read_write, t.companies,
+                to_char(t.created_at, 'YYYY-MM-DD HH24:MI:SS TZ'),
+                to_char(t.last_used_at, 'YYYY-MM-DD HH24:MI:SS TZ')
+            FROM
+                bimdb.api_tokens t
+                INNER JOIN bimdb.users u ON u.id = t.user_id
+            ORDER BY
+                t.id
+        ",
+        &[],
+    ).await;
+    let token_rows = match token_rows_res {
+        Ok(tr) => tr,
+        Err(e) => {
+            error!("failed to obtain API tokens: {}", e);
+            return return_500();
+        },
+    };
+    let tokens = token_rows.into_iter()
+        .map(|row| TokenPart {
+            id: row.get(0),
+            username: row.get(1),
+            description: row.get(2),
+            read_write: row.get(3),
+            companies: row.get(4),
+            created_at: row.get(5),
+            last_used_at: row.get(6),
+        })
+        .collect();
+
+    let usernames = match db_conn.query("SELECT username FROM bimdb.users ORDER BY username", &[]).await {
+        Ok(rows) => rows.into_iter().map(|row| row.get(0)).collect(),
+        Err(e) => {
+            error!("failed to obtain usernames: {}", e);
+            return return_500();
+        },
+    };
+
+    let template = TokensTemplate {
+        base_path: config::current().http.base_path.clone(),
+        tokens,
+        usernames,
+        new_token,
+    };
+    let template_text = template.render()
+        .expect("failed to render template");
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Full::new(Bytes::from(template_text)))
+        .unwrap_or_else(|_| return_500())
+}
+
+/// Handles the API token administration page, which requires administrator rights for all
+/// companies.
+///
+/// A newly created token is only shown once; the database only stores its hash.
+#[instrument(skip_all)]
+pub(crate) async fn handle_tokens(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    if !principal.may_administer(ALL_COMPANIES) {
+        return return_403("only administrators of all companies may manage API tokens");
+    }
+
+    let db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+
+    if request.method() == Method::GET {
+        return render_tokens(&db_conn, None).await;
+    } else if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::GET, Method::POST]);
+    }
+
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return return_500();
+        },
+    };
+    let form_values: HashMap<Cow<str>, Cow<str>> = form_urlencoded::parse(&request_bytes)
+        .collect();
+
+    match form_values.get("action").map(|a| a.as_ref()) {
+        Some("create-token") => {
+            let username = form_values.get("username")
+                .map(|u| u.trim())
+                .unwrap_or("");
+            let description = form_values.get("description")
+                .map(|d| d.trim())
+                .unwrap_or("");
+            if description.is_empty() {
+                return return_400("field 'description' must not be empty");
+            }
+            if description.chars().count() > 256 {
+                return return_400("field 'description' must not be longer than 256 characters");
+            }
+            let read_write = match form_values.get("access").map(|a| a.as_ref()) {
+                Some("read-only") => false,
+                Some("read-write") => true,
+                _ => return return_400("invalid value for field 'access'"),
+            };
+            let companies: Vec<String> = form_values.get("companies")
+                .map(|c| c.as_ref())
+                .unwrap_or("")
+                .split('\n')
+                .map(|c| c.trim())
+                .filter(|c| !c.is_empty())
+                .map(|c| c.to_owned())
+                .collect();
+            if companies.iter().any(|c| c.chars().count() > 256) {
+                return return_400("companies must not be longer than 256 characters");
+            }
+            let companies_opt = if !companies.is_empty() { Some(companies) } else { None };
+
+            let user_id = match find_user_id(&db_conn, username).await {
+                Ok(Some(ui)) => ui,
+                Ok(None) => return return_400("failed to find this user"),
+                Err(e) => {
+                    error!("failed to find user {:?}: {}", username, e);
+                    return return_500();
+                },
+            };
+            let token = generate_token();
+            let insert_res = db_conn.query_one(
+                "
+                    INSERT INTO bimdb.api_tokens
+                        (id, token_hash, user_id, description, read_write, companies)
+                    VALUES
+                        (DEFAULT, $1, $2, $3, $4, $5)
+                    RETURNING id
+                ",
+                &[&hash_token(&token), &user_id, &description, &read_write, &companies_opt],
+            ).await;
+            let token_id: i64 = match insert_res {
+                Ok(row) => row.get(0),
+                Err(e) => {
+                    error!("failed to create API token: {}", e);
+                    return return_500();
+                },
+            };
+            info!(
+                "{} ({:?}) created API token {} for user {:?}",
+                remote_addr, principal.username, token_id, username,
+            );
+            render_tokens(&db_conn, Some(token)).await
+        },
+        Some("revoke-token") => {
+            let token_id: i64 = match form_values.get("id").map(|i| i.parse()) {
+                Some(Ok(ti)) => ti,
+                Some(Err(_)) => return return_400("invalid value for field 'id'"),
+                None => return return_400("field 'id' is required"),
+            };
+            let affected_rows = match db_conn.execute("DELETE FROM bimdb.api_tokens WHERE id = $1", &[&token_id]).await {
+                Ok(ar) => ar,
+                Err(e) => {
+                    error!("failed to revoke API token {}: {}", token_id, e);
+                    return return_500();
+                },
+            };
+            if affected_rows == 0 {
+                return return_400("failed to find this token");
+            }
+            info!("{} ({:?}) revoked API token {}", remote_addr, principal.username, token_id);
+            redirect_to("tokens")
+        },
+        _ => return_400("invalid value for field 'action'"),
+    }
+}
@@ -17,8 +17,7 @@ impl<K: Ord, V> ValueMultiset<K, V> {
     pub fn get_first<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
             where K: Borrow<Q> {
         self.key_to_values
-            .get(key)?
-            .get(0)
+            .get(key)?.first()
     }
 
     pub fn get_last<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
@@ -0,0 +1,566 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+
+use askama::Template;
+use deadpool_postgres::GenericClient;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, Response};
+use hyper::body::{Bytes, Incoming};
+use tokio_postgres::Transaction;
+use tokio_postgres::error::SqlState;
+use tracing::{error, info, instrument};
+
+use crate::{db_connect, return_400, return_403, return_405, return_409, return_500, ExportedVehicle};
+use crate::auth::{self, ALL_COMPANIES};
+use crate::config::{self, ValueSetConfig};
+
+
+/// A value which may be assigned to vehicles.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct AllowedValue {
+    pub value: String,
+    pub description: String,
+    pub display_order: i64,
+
+    /// The companies to which this value has been assigned.
+    pub companies: BTreeSet<String>,
+}
+impl AllowedValue {
+    /// Returns the companies to which this value has been assigned as a JSON array, for use by
+    /// scripts.
+    pub fn companies_json(&self) -> String {
+        serde_json::to_string(&self.companies)
+            .expect("failed to serialize companies")
+    }
+}
+
+/// The allowed values of one kind, in display order.
+///
+/// A company to which some of the values have been assigned may only use those; every other company
+/// may use all of them.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct ValueSet {
+    pub values: Vec<AllowedValue>,
+}
+impl ValueSet {
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns whether some of the values have been assigned to the given company.
+    pub fn restricts(&self, company: &str) -> bool {
+        self.values.iter().any(|v| v.companies.contains(company))
+    }
+
+    /// Returns whether the given company may use the given value.
+    pub fn allows(&self, company: &str, value: &str) -> bool {
+        let restricted = self.restricts(company);
+        self.values.iter()
+            .any(|v| v.value == value && (!restricted || v.companies.contains(company)))
+    }
+
+    /// Returns the options of a select field for this set, marking those which are offered to the
+    /// given company (all of them if no company has been picked yet) and those which are selected.
+    ///
+    /// Selected values are always offered; those which are not part of the set are appended so that
+    /// editing a vehicle does not silently drop them. An empty set has no options at all, which
+    /// makes optional fields fall back to free text.
+    pub fn options(&self, company: Option<&str>, selected: &[&str]) -> Vec<ValueOption> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let restricted = company.map(|c| self.restricts(c)).unwrap_or(false);
+        let mut options: Vec<ValueOption> = self.values.iter()
+            .map(|v| {
+                let is_selected = selected.contains(&v.value.as_str());
+                let offered = is_selected
+                    || !restricted
+                    || company.map(|c| v.companies.contains(c)).unwrap_or(true);
+                ValueOption {
+                    allowed: v.clone(),
+                    offered,
+                    selected: is_selected,
+                }
+            })
+            .collect();
+        for value in selected {
+            if !self.values.iter().any(|v| v.value == *value) {
+                options.push(ValueOption {
+                    allowed: AllowedValue {
+                        value: (*value).to_owned(),
+                        description: String::new(),
+                        display_order: 0,
+                        companies: BTreeSet::new(),
+                    },
+                    offered: true,
+                    selected: true,
+                });
+            }
+        }
+        options
+    }
+}
+
+/// An option of a select field for a value set.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ValueOption {
+    pub allowed: AllowedValue,
+    pub offered: bool,
+    pub selected: bool,
+}
+
+/// The values which may be assigned to vehicles.
+///
+/// Vehicle classes and power sources are always restricted to their sets. Depots and manufacturers
+/// are only restricted once their sets contain values.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct ValueSets {
+    pub veh_classes: ValueSet,
+    pub power_sources: ValueSet,
+    pub depots: ValueSet,
+    pub manufacturers: ValueSet,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum ValueSetKind {
+    VehClass,
+    PowerSource,
+    Depot,
+    Manufacturer,
+}
+impl ValueSetKind {
+    pub fn from_form(value: &str) -> Option<Self> {
+        match value {
+            "veh-class" => Some(Self::VehClass),
+            "power-source" => Some(Self::PowerSource),
+            "depot" => Some(Self::Depot),
+            "manufacturer" => Some(Self::Manufacturer),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> &'static str {
+        match self {
+            Self::VehClass => "bimdb.allowed_veh_classes",
+            Self::PowerSource => "bimdb.allowed_power_sources",
+            Self::Depot => "bimdb.allowed_depots",
+            Self::Manufacturer => "bimdb.allowed_manufacturers",
+        }
+    }
+
+    /// The table which assigns values to companies.
+    pub fn company_table(&self) -> &'static str {
+        match self {
+            Self::VehClass => "bimdb.company_veh_classes",
+            Self::PowerSource => "bimdb.company_power_sources",
+            Self::Depot => "bimdb.company_depots",
+            Self::Manufacturer => "bimdb.company_manufacturers",
+        }
+    }
+
+    pub fn column(&self) -> &'static str {
+        match self {
+            Self::VehClass => "veh_class",
+            Self::PowerSource => "power_source",
+            Self::Depot => "depot",
+            Self::Manufacturer => "manufacturer",
+        }
+    }
+
+    /// The maximum length of a value in characters, matching the column in the vehicle tables.
+    pub fn max_value_chars(&self) -> usize {
+        match self {
+            Self::VehClass => 32,
+            Self::PowerSource => 256,
+            Self::Depot => 256,
+            Self::Manufacturer => 32,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::VehClass => "vehicle class",
+            Self::PowerSource => "power source",
+            Self::Depot => "depot",
+            Self::Manufacturer => "manufacturer",
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "value_sets.html")]
+struct ValueSetsTemplate {
+    pub base_path: String,
+    pub value_sets: ValueSets,
+}
+
+
+fn redirect_to(page: &str) -> Response<Full<Bytes>> {
+    let base_path = &config::current()
+        .http.base_path;
+    Response::builder()
+        .status(302)
+        .header("Location", format!("{}/{}", base_path, page))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("redirecting...")))
+        .unwrap_or_else(|_| return_500())
+}
+
+async fn load_value_set<C: GenericClient>(db_conn: &C, kind: ValueSetKind) -> Option<ValueSet> {
+    let query = format!(
+        "
+            SELECT
+                a.{col}, a.description, a.display_order, c.company
+            FROM
+                {tbl} a
+                LEFT OUTER JOIN {company_tbl} c ON c.{col} = a.{col}
+            ORDER BY
+                a.display_order, a.{col}
+        ",
+        col = kind.column(), tbl = kind.table(), company_tbl = kind.company_table(),
+    );
+    let rows = match db_conn.query(&query, &[]).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to obtain allowed values of {}: {}", kind.table(), e);
+            return None;
+        },
+    };
+
+    let mut values: Vec<AllowedValue> = Vec::new();
+    for row in rows {
+        let value: String = row.get(0);
+        if values.last().map(|v| v.value != value).unwrap_or(true) {
+            values.push(AllowedValue {
+                value,
+                description: row.get(1),
+                display_order: row.get(2),
+                companies: BTreeSet::new(),
+            });
+        }
+        let company: Option<String> = row.get(3);
+        if let Some(company) = company {
+            values.last_mut().expect("value was just pushed")
+                .companies.insert(company);
+        }
+    }
+    Some(ValueSet { values })
+}
+
+/// Loads the allowed values and their assignments to companies from the database.
+pub(crate) async fn load_value_sets<C: GenericClient>(db_conn: &C) -> Option<ValueSets> {
+    let veh_classes = load_value_set(db_conn, ValueSetKind::VehClass).await?;
+    let power_sources = load_value_set(db_conn, ValueSetKind::PowerSource).await?;
+    let depots = load_value_set(db_conn, ValueSetKind::Depot).await?;
+    let manufacturers = load_value_set(db_conn, ValueSetKind::Manufacturer).await?;
+    Some(ValueSets {
+        veh_classes,
+        power_sources,
+        depots,
+        manufacturers,
+    })
+}
+
+/// Adds the value sets from the configuration file to the database, skipping values which are
+/// already allowed.
+///
+/// Returns the number of vehicle classes and power sources which have been added.
+pub(crate) async fn import_config_value_sets(db_txn: &Transaction<'_>, value_sets: &ValueSetConfig) -> Result<(u64, u64), tokio_postgres::Error> {
+    let mut added_veh_classes = 0;
+    for veh_class in &value_sets.vehicle_classes {
+        added_veh_classes += db_txn.execute(
+            "INSERT INTO bimdb.allowed_veh_classes (veh_class) VALUES ($1) ON CONFLICT DO NOTHING",
+            &[veh_class],
+        ).await?;
+    }
+    let mut added_power_sources = 0;
+    for power_source in &value_sets.power_sources {
+        added_power_sources += db_txn.execute(
+            "INSERT INTO bimdb.allowed_power_sources (power_source) VALUES ($1) ON CONFLICT DO NOTHING",
+            &[power_source],
+        ).await?;
+    }
+    Ok((added_veh_classes, added_power_sources))
+}
+
+/// Handles the value sets page, on which administrators of all companies manage the allowed vehicle
+/// classes, power sources, depots and manufacturers and assign them to companies.
+#[instrument(skip_all)]
+pub(crate) async fn handle_value_sets(remote_addr: SocketAddr, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let Some(principal) = auth::principal(&request) else {
+        return return_403("you must be logged in");
+    };
+    if !principal.may_administer(ALL_COMPANIES) {
+        return return_403("only administrators of all companies may manage value sets");
+    }
+
+    let db_conn = match db_connect().await {
+        Ok(dbc) => dbc,
+        Err(e) => return e.response(),
+    };
+
+    if request.method() == Method::GET {
+        let value_sets = match load_value_sets(&db_conn).await {
+            Some(vs) => vs,
+            None => return return_500(),
+        };
+        let template = ValueSetsTemplate {
+            base_path: config::current().http.base_path.clone(),
+            value_sets,
+        };
+        let template_text = template.render()
+            .expect("failed to render template");
+        return Response::builder()
+            .status(200)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(template_text)))
+            .unwrap_or_else(|_| return_500());
+    } else if request.method() != Method::POST {
+        return return_405(request.method(), &[Method::GET, Method::POST]);
+    }
+
+    let (_request_head, request_body) = request.into_parts();
+    let request_bytes = match request_body.collect().await {
+        Ok(rb) => rb.to_bytes(),
+        Err(e) => {
+            error!("failed to read request bytes: {}", e);
+            return return_500();
+        },
+    };
+    let form_values: HashMap<Cow<str>, Cow<str>> = form_urlencoded::parse(&request_bytes)
+        .collect();
+    let kind = match form_values.get("set").and_then(|s| ValueSetKind::from_form(s)) {
+        Some(k) => k,
+        None => return return_400("invalid value for field 'set'"),
+    };
+    let value = form_values.get("value")
+        .map(|v| v.trim())
+        .unwrap_or("");
+    if value.is_empty() {
+        return return_400("field 'value' must not be empty");
+    }
+    if value.chars().count() > kind.max_value_chars() {
+        return return_400(&format!("field 'value' must not be longer than {} characters", kind.max_value_chars()));
+    }
+    let description = form_values.get("description")
+        .map(|d| d.trim())
+        .unwrap_or("");
+    if description.chars().count() > 256 {
+        return return_400("field 'description' must not be longer than 256 characters");
+    }
+    let display_order: i64 = match form_values.get("display-order").map(|o| o.trim()) {
+        None | Some("") => 0,
+        Some(o) => match o.parse() {
+            Ok(i) => i,
+            Err(_) => return return_400("invalid value for field 'display-order'"),
+        },
+    };
+
+    match form_values.get("action").map(|a| a.as_ref()) {
+        Some("add") => {
+            let insert_res = db_conn.execute(
+                &format!(
+                    "INSERT INTO {} ({}, description, display_order) VALUES ($1, $2, $3)",
+                    kind.table(), kind.column(),
+                ),
+                &[&value, &description, &display_order],
+            ).await;
+            if let Err(e) = insert_res {
+                if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    return return_409(&format!("this {} is already allowed", kind.description()));
+                }
+                error!("failed to add {} {:?}: {}", kind.description(), value, e);
+                return return_500();
+            }
+            info!("{} ({:?}) added {} {:?}", remote_addr, principal.username, kind.description(), value);
+        },
+        Some("update") => {
+            let update_res = db_conn.execute(
+                &format!(
+                    "UPDATE {} SET description = $2, display_order = $3 WHERE {} = $1",
+                    kind.table(), kind.column(),
+                ),
+                &[&value, &description, &display_order],
+            ).await;
+            match update_res {
+                Ok(0) => return return_400(&format!("this {} is not allowed", kind.description())),
+                Ok(_) => {},
+                Err(e) => {
+                    error!("failed to update {} {:?}: {}", kind.description(), value, e);
+                    return return_500();
+                },
+            }
+            info!("{} ({:?}) updated {} {:?}", remote_addr, principal.username, kind.description(), value);
+        },
+        Some("delete") => {
+            let delete_res = db_conn.execute(
+                &format!("DELETE FROM {} WHERE {} = $1", kind.table(), kind.column()),
+                &[&value],
+            ).await;
+            match delete_res {
+                Ok(0) => return return_400(&format!("this {} is not allowed", kind.description())),
+                Ok(_) => {},
+                Err(e) => {
+                    if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                        return return_409(&format!(
+                            "this {} is still assigned to vehicles (including those in the trash)",
+                            kind.description(),
+                        ));
+                    }
+                    error!("failed to delete {} {:?}: {}", kind.description(), value, e);
+                    return return_500();
+                },
+            }
+            info!("{} ({:?}) deleted {} {:?}", remote_addr, principal.username, kind.description(), value);
+        },
+        Some("assign") => {
+            let company = form_values.get("company")
+                .map(|c| c.trim())
+                .unwrap_or("");
+            if let Err(e) = ExportedVehicle::validate_company(company) {
+                return return_400(&e);
+            }
+            let insert_res = db_conn.execute(
+                &format!(
+                    "INSERT INTO {} (company, {}) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                    kind.company_table(), kind.column(),
+                ),
+                &[&company, &value],
+            ).await;
+            if let Err(e) = insert_res {
+                if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                    return return_400(&format!("this {} is not allowed", kind.description()));
+                }
+                error!("failed to assign {} {:?} to company {:?}: {}", kind.description(), value, company, e);
+                return return_500();
+            }
+            info!(
+                "{} ({:?}) assigned {} {:?} to company {:?}",
+                remote_addr, principal.username, kind.description(), value, company,
+            );
+        },
+        Some("unassign") => {
+            let company = form_values.get("company")
+                .map(|c| c.trim())
+                .unwrap_or("");
+            let delete_res = db_conn.execute(
+                &format!("DELETE FROM {} WHERE company = $1 AND {} = $2", kind.company_table(), kind.column()),
+                &[&company, &value],
+            ).await;
+            if let Err(e) = delete_res {
+                error!("failed to unassign {} {:?} from company {:?}: {}", kind.description(), value, company, e);
+                return return_500();
+            }
+            info!(
+                "{} ({:?}) unassigned {} {:?} from company {:?}",
+                remote_addr, principal.username, kind.description(), value, company,
+            );
+        },
+        _ => return return_400("invalid value for field 'action'"),
+    }
+
+    redirect_to("value-sets")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_set(values: &[(&str, &[&str])]) -> ValueSet {
+        let values = values.iter()
+            .enumerate()
+            .map(|(i, (value, companies))| AllowedValue {
+                value: (*value).to_owned(),
+                description: String::new(),
+                display_order: i.try_into().unwrap(),
+                companies: companies.iter().map(|c| (*c).to_owned()).collect(),
+            })
+            .collect();
+        ValueSet { values }
+    }
+
+    fn depots() -> ValueSet {
+        value_set(&[
+            ("Favoriten", &["Wien"]),
+            ("Rudolfsheim", &["Wien"]),
+            ("Steyrergasse", &["Graz"]),
+            ("Remise", &[]),
+        ])
+    }
+
+    /// Returns the offered and the selected values.
+    fn summarize(options: &[ValueOption]) -> (Vec<&str>, Vec<&str>) {
+        let offered = options.iter()
+            .filter(|o| o.offered)
+            .map(|o| o.allowed.value.as_str())
+            .collect();
+        let selected = options.iter()
+            .filter(|o| o.selected)
+            .map(|o| o.allowed.value.as_str())
+            .collect();
+        (offered, selected)
+    }
+
+    #[test]
+    fn test_company_without_assignments() {
+        let depots = depots();
+        assert!(!depots.restricts("Linz"));
+        assert!(depots.allows("Linz", "Favoriten"));
+        assert!(depots.allows("Linz", "Steyrergasse"));
+        assert!(depots.allows("Linz", "Remise"));
+        assert!(!depots.allows("Linz", "Hauptbahnhof"));
+        assert_eq!(
+            summarize(&depots.options(Some("Linz"), &[])),
+            (vec!["Favoriten", "Rudolfsheim", "Steyrergasse", "Remise"], vec![]),
+        );
+    }
+
+    #[test]
+    fn test_company_with_assignments() {
+        let depots = depots();
+        assert!(depots.restricts("Wien"));
+        assert!(depots.allows("Wien", "Favoriten"));
+        assert!(depots.allows("Wien", "Rudolfsheim"));
+        assert!(!depots.allows("Wien", "Steyrergasse"));
+        assert!(!depots.allows("Wien", "Remise"));
+
+        let options = depots.options(Some("Wien"), &["Rudolfsheim"]);
+        assert_eq!(options.len(), 4);
+        assert_eq!(summarize(&options), (vec!["Favoriten", "Rudolfsheim"], vec!["Rudolfsheim"]));
+    }
+
+    #[test]
+    fn test_no_company() {
+        let depots = depots();
+        assert_eq!(
+            summarize(&depots.options(None, &[])),
+            (vec!["Favoriten", "Rudolfsheim", "Steyrergasse", "Remise"], vec![]),
+        );
+    }
+
+    #[test]
+    fn test_selected_values_are_kept() {
+        let depots = depots();
+
+        // stored before Graz was limited to Steyrergasse
+        let options = depots.options(Some("Graz"), &["Remise"]);
+        assert_eq!(summarize(&options), (vec!["Steyrergasse", "Remise"], vec!["Remise"]));
+
+        // removed from the set since
+        let options = depots.options(Some("Graz"), &["Eggenberg"]);
+        assert_eq!(options.len(), 5);
+        assert_eq!(summarize(&options), (vec!["Steyrergasse", "Eggenberg"], vec!["Eggenberg"]));
+        assert!(options[4].allowed.companies.is_empty());
+        assert!(!depots.allows("Graz", "Eggenberg"));
+    }
+
+    #[test]
+    fn test_empty_set() {
+        let empty = ValueSet::default();
+        assert!(!empty.restricts("Wien"));
+        assert!(!empty.allows("Wien", "Favoriten"));
+        assert!(empty.options(Some("Wien"), &["Favoriten"]).is_empty());
+    }
+}
@@ -1,2 +1,2 @@
-!function(e,t,n,r,l){var o="u">typeof globalThis?globalThis:"u">typeof self?self:"u">typeof window?window:"u">typeof global?global:{},u="function"==typeof o[r]&&o[r],d=u.i||{},i=u.cache||{},a="u">typeof module&&"function"==typeof module.require&&module.require.bind(module);function c(t,n){if(!i[t]){if(!e[t]){if(l[t])return l[t];var d="function"==typeof o[r]&&o[r];if(!n&&d)return d(t,!0);if(u)return u(t,!0);if(a&&"string"==typeof t)return a(t);var s=Error("Cannot find module '"+t+"'");throw s.code="MODULE_NOT_FOUND",s}f.resolve=function(n){var r=e[t][1][n];return null!=r?r:n},f.cache={};var p=i[t]=new c.Module(t);e[t][0].call(p.exports,f,p,p.exports,o)}return i[t].exports;function f(e){var t=f.resolve(e);if(!1===t)return{};if(Array.isArray(t)){var n={__esModule:!0};return t.forEach(function(e){var t=e[0],r=e[1],l=e[2]||e[0],o=c(r);"*"===t?Object.keys(o).forEach(function(e){"default"===e||"__esModule"===e||Object.prototype.hasOwnProperty.call(n,e)||Object.defineProperty(n,e,{enumerable:!0,get:function(){return o[e]}})}):"*"===l?Object.defineProperty(n,t,{enumerable:!0,value:o}):Object.defineProperty(n,t,{enumerable:!0,get:function(){return"default"===l?o.__esModule?o.default:o:o[l]}})}),n}return c(t)}}c.isParcelRequire=!0,c.Module=function(e){this.id=e,this.bundle=c,this.require=a,this.exports={}},c.modules=e,c.cache=i,c.parent=u,c.distDir=void 0,c.publicUrl=void 0,c.devServer=void 0,c.i=d,c.register=function(t,n){e[t]=[function(e,t){t.exports=n},{}]},Object.defineProperty(c,"root",{get:function(){return o[r]}}),o[r]=c;for(var s=0;s<t.length;s++)c(t[s]);if(n){var p=c(n);"object"==typeof exports&&"u">typeof module?module.exports=p:"function"==typeof define&&define.amd&&define(function(){return p})}}({bvhvx:[function(e,t,n,r){var l,o,u=e("@parcel/transformer-js/src/esmodule-helpers.js");u.defineInteropFlag(n),u.export(n,"BimDatabase",()=>o);var d=e("./add_edit"),i=e("./coupling_add_edit");(l=o||(o={})).setUpAddEdit=function(){document.addEventListener("DOMContentLoaded",d.AddEdit.doSetUp)},l.setUpCouplingAddEdit=function(){document.addEventListener("DOMContentLoaded",i.CouplingAddEdit.doSetUp)},window.BimDatabase=o},{"./add_edit":"k4LrC","./coupling_add_edit":"821xA","@parcel/transformer-js/src/esmodule-helpers.js":"8YXJJ"}],k4LrC:[function(e,t,n,r){var l,o=e("@parcel/transformer-js/src/esmodule-helpers.js");function u(e,t){let n=document.createElement("div");n.classList.add("other-data-entry"),e.insertBefore(n,t);let r=document.createElement("input");r.type="text",r.classList.add("key"),n.appendChild(r);let l=document.createElement("input");l.type="text",l.classList.add("value"),n.appendChild(l);let o=document.createElement("input");return o.type="button",o.value="−",o.addEventListener("click",()=>n.parentNode?.removeChild(n)),n.appendChild(o),[r,l]}o.defineInteropFlag(n),o.export(n,"AddEdit",()=>l),(l||(l={})).doSetUp=function(){let e=document.getElementById("bimdb-ae-other-data");if(null===e)return;let t=e.parentElement;if(null===t)return;let n=e.form;if(null===n)return;n.addEventListener("submit",r=>(function(e,t,n,r){r.preventDefault();let l=Array.prototype.slice.call(t.querySelectorAll("div.other-data-entry"),0),o={};for(let e of l){let t=e.querySelector("input.key");if(null===t)continue;let n=e.querySelector("input.value");null!==n&&(o[t.value]=n.value)}for(let e of(n.value=JSON.stringify(o),l))e.parentNode?.removeChild(e);e.submit()})(n,t,e,r));let r=document.createElement("div");r.classList.add("add-other-data-entry"),t.appendChild(r);let l=JSON.parse(e.value);for(let e of Object.keys(l)){let n=l[e],[o,d]=u(t,r);o.value=e,d.value=n}let o=document.createElement("input");o.type="button",o.value="+",o.addEventListener("click",()=>{let[e,n]=u(t,r);e.focus()}),r.appendChild(o),e.style.display="none";let d=document.getElementById("bimdb-ae-company");null!==d&&d.focus()}},{"@parcel/transformer-js/src/esmodule-helpers.js":"8YXJJ"}],"8YXJJ":[function(e,t,n,r){n.interopDefault=function(e){return e&&e.__esModule?e:{default:e}},n.defineInteropFlag=function(e){Object.defineProperty(e,"__esModule",{value:!0})},n.exportAll=function(e,t){return Object.keys(e).forEach(function(n){"default"===n||"__esModule"===n||Object.prototype.hasOwnProperty.call(t,n)||Object.defineProperty(t,n,{enumerable:!0,get:function(){return e[n]}})}),t},n.export=function(e,t,n){Object.defineProperty(e,t,{enumerable:!0,get:n})}},{}],"821xA":[function(e,t,n,r){var l,o=e("@parcel/transformer-js/src/esmodule-helpers.js");function u(e){let t=e.querySelectorAll(".vehicle-entry");for(let e=0;e<t.length;e++){let n=t.item(e),r=n.querySelector(".up-button");null!==r&&(r.disabled=0===e);let l=n.querySelector(".down-button");null!==l&&(l.disabled=e===t.length-1)}}function d(e,t){let n=document.createElement("div");n.classList.add("vehicle-entry"),e.insertBefore(n,t);let r=document.createElement("input");r.type="text",r.classList.add("vehicle-number"),n.appendChild(r);let l=document.createElement("input");l.type="button",l.value="−",l.addEventListener("click",()=>{n.parentNode?.removeChild(n),u(e)}),n.appendChild(l);let o=document.createElement("input");o.type="button",o.classList.add("up-button"),o.value="↑",o.addEventListener("click",()=>{n.parentNode?.insertBefore(n,n.previousElementSibling),u(e)}),n.appendChild(o);let d=document.createElement("input");return d.type="button",d.classList.add("down-button"),d.value="↓",d.addEventListener("click",()=>{let t,r;return r=null!==(t=n.nextElementSibling)?t.nextElementSibling:null,void(n.parentNode?.insertBefore(n,r),u(e))}),n.appendChild(d),u(e),r}o.defineInteropFlag(n),o.export(n,"CouplingAddEdit",()=>l),(l||(l={})).doSetUp=function(){let e=document.getElementById("bimdb-cae-vehicles");if(null===e)return;let t=e.parentElement;if(null===t)return;let n=e.form;if(null===n)return;n.addEventListener("submit",r=>(function(e,t,n,r){r.preventDefault();let l=Array.prototype.slice.call(t.querySelectorAll(".vehicle-entry"),0),o=[];for(let e of l){let t=e.querySelector("input.vehicle-number");null!==t&&o.push(t.value)}for(let e of(n.value=o.join("\n"),l))e.parentNode?.removeChild(e);e.submit()})(n,t,e,r));let r=document.createElement("div");for(let n of(r.classList.add("add-vehicle"),t.appendChild(r),e.value.split("\n").map(e=>e.trim()).filter(e=>e.length>0)))d(t,r).value=n;let l=document.createElement("input");l.type="button",l.value="+",l.addEventListener("click",()=>{d(t,r).focus()}),r.appendChild(l),e.style.display="none";let o=document.getElementById("bimdb-cae-company");null!==o&&o.focus()}},{"@parcel/transformer-js/src/esmodule-helpers.js":"8YXJJ"}]},["bvhvx"],"bvhvx","parcelRequire4688",{});
+!function(e,t,n,r,l){var o="u">typeof globalThis?globalThis:"u">typeof self?self:"u">typeof window?window:"u">typeof global?global:{},u="function"==typeof o[r]&&o[r],d=u.i||{},i=u.cache||{},a="u">typeof module&&"function"==typeof module.require&&module.require.bind(module);function c(t,n){if(!i[t]){if(!e[t]){if(l[t])return l[t];var d="function"==typeof o[r]&&o[r];if(!n&&d)return d(t,!0);if(u)return u(t,!0);if(a&&"string"==typeof t)return a(t);var s=Error("Cannot find module '"+t+"'");throw s.code="MODULE_NOT_FOUND",s}f.resolve=function(n){var r=e[t][1][n];return null!=r?r:n},f.cache={};var p=i[t]=new c.Module(t);e[t][0].call(p.exports,f,p,p.exports,o)}return i[t].exports;function f(e){var t=f.resolve(e);if(!1===t)return{};if(Array.isArray(t)){var n={__esModule:!0};return t.forEach(function(e){var t=e[0],r=e[1],l=e[2]||e[0],o=c(r);"*"===t?Object.keys(o).forEach(function(e){"default"===e||"__esModule"===e||Object.prototype.hasOwnProperty.call(n,e)||Object.defineProperty(n,e,{enumerable:!0,get:function(){return o[e]}})}):"*"===l?Object.defineProperty(n,t,{enumerable:!0,value:o}):Object.defineProperty(n,t,{enumerable:!0,get:function(){return"default"===l?o.__esModule?o.default:o:o[l]}})}),n}return c(t)}}c.isParcelRequire=!0,c.Module=function(e){this.id=e,this.bundle=c,this.require=a,this.exports={}},c.modules=e,c.cache=i,c.parent=u,c.distDir=void 0,c.publicUrl=void 0,c.devServer=void 0,c.i=d,c.register=function(t,n){e[t]=[function(e,t){t.exports=n},{}]},Object.defineProperty(c,"root",{get:function(){return o[r]}}),o[r]=c;for(var s=0;s<t.length;s++)c(t[s]);if(n){var p=c(n);"object"==typeof exports&&"u">typeof module?module.exports=p:"function"==typeof define&&define.amd&&define(function(){return p})}}({bvhvx:[function(e,t,n,r){var l,o,u=e("@parcel/transformer-js/src/esmodule-helpers.js");u.defineInteropFlag(n),u.export(n,"BimDatabase",()=>o);var d=e("./add_edit"),i=e("./coupling_add_edit");(l=o||(o={})).setUpAddEdit=function(){document.addEventListener("DOMContentLoaded",d.AddEdit.doSetUp)},l.setUpCouplingAddEdit=function(){document.addEventListener("DOMContentLoaded",i.CouplingAddEdit.doSetUp)},window.BimDatabase=o},{"./add_edit":"k4LrC","./coupling_add_edit":"821xA","@parcel/transformer-js/src/esmodule-helpers.js":"8YXJJ"}],k4LrC:[function(e,t,n,r){var l,o=e("@parcel/transformer-js/src/esmodule-helpers.js");function u(e,t){let n=document.createElement("div");n.classList.add("other-data-entry"),e.insertBefore(n,t);let r=document.createElement("input");r.type="text",r.classList.add("key"),n.appendChild(r);let l=document.createElement("input");l.type="text",l.classList.add("value"),n.appendChild(l);let o=document.createElement("input");return o.type="button",o.value="−",o.addEventListener("click",()=>n.parentNode?.removeChild(n)),n.appendChild(o),[r,l]}function updateValueSetOptions(e,t){let n=e.value.trim();for(let r of t){let t=Array.prototype.slice.call(r.options,0),l=t.map(e=>JSON.parse(e.dataset.companies??"[]")),o=l.some(e=>-1!==e.indexOf(n)),u=null,i=!1;for(let e=0;e<t.length;e++){let r=t[e],a=""===r.value||void 0!==r.dataset.stored||!o||-1!==l[e].indexOf(n);r.hidden=!a,r.disabled=!a,a&&null===u&&(u=r),!a&&r.selected&&(r.selected=!1,i=!0)}i&&!r.multiple&&null!==u&&(u.selected=!0)}}o.defineInteropFlag(n),o.export(n,"AddEdit",()=>l),(l||(l={})).doSetUp=function(){let e=document.getElementById("bimdb-ae-other-data");if(null===e)return;let t=e.parentElement;if(null===t)return;let n=e.form;if(null===n)return;n.addEventListener("submit",r=>(function(e,t,n,r){r.preventDefault();let l=Array.prototype.slice.call(t.querySelectorAll("div.other-data-entry"),0),o={};for(let e of l){let t=e.querySelector("input.key");if(null===t)continue;let n=e.querySelector("input.value");null!==n&&(o[t.value]=n.value)}for(let e of(n.value=JSON.stringify(o),l))e.parentNode?.removeChild(e);e.submit()})(n,t,e,r));let r=document.createElement("div");r.classList.add("add-other-data-entry"),t.appendChild(r);let l=JSON.parse(e.value);for(let e of Object.keys(l)){let n=l[e],[o,d]=u(t,r);o.value=e,d.value=n}let o=document.createElement("input");o.type="button",o.value="+",o.addEventListener("click",()=>{let[e,n]=u(t,r);e.focus()}),r.appendChild(o),e.style.display="none";let d=document.getElementById("bimdb-ae-company"),a=[];for(let e of["bimdb-ae-veh-class","bimdb-ae-power-sources","bimdb-ae-manufacturer","bimdb-ae-depot"]){let t=document.getElementById(e);t instanceof HTMLSelectElement&&a.push(t)}null!==d&&d.addEventListener("change",()=>updateValueSetOptions(d,a)),null!==d&&d.focus()}},{"@parcel/transformer-js/src/esmodule-helpers.js":"8YXJJ"}],"8YXJJ":[function(e,t,n,r){n.interopDefault=function(e){return e&&e.__esModule?e:{default:e}},n.defineInteropFlag=function(e){Object.defineProperty(e,"__esModule",{value:!0})},n.exportAll=function(e,t){return Object.keys(e).forEach(function(n){"default"===n||"__esModule"===n||Object.prototype.hasOwnProperty.call(t,n)||Object.defineProperty(t,n,{enumerable:!0,get:function(){return e[n]}})}),t},n.export=function(e,t,n){Object.defineProperty(e,t,{enumerable:!0,get:n})}},{}],"821xA":[function(e,t,n,r){var l,o=e("@parcel/transformer-js/src/esmodule-helpers.js");function u(e){let t=e.querySelectorAll(".vehicle-entry");for(let e=0;e<t.length;e++){let n=t.item(e),r=n.querySelector(".up-button");null!==r&&(r.disabled=0===e);let l=n.querySelector(".down-button");null!==l&&(l.disabled=e===t.length-1)}}function d(e,t){let n=document.createElement("div");n.classList.add("vehicle-entry"),e.insertBefore(n,t);let r=document.createElement("input");r.type="text",r.classList.add("vehicle-number"),n.appendChild(r);let l=document.createElement("input");l.type="button",l.value="−",l.addEventListener("click",()=>{n.parentNode?.removeChild(n),u(e)}),n.appendChild(l);let o=document.createElement("input");o.type="button",o.classList.add("up-button"),o.value="↑",o.addEventListener("click",()=>{n.parentNode?.insertBefore(n,n.previousElementSibling),u(e)}),n.appendChild(o);let d=document.createElement("input");return d.type="button",d.classList.add("down-button"),d.value="↓",d.addEventListener("click",()=>{let t,r;return r=null!==(t=n.nextElementSibling)?t.nextElementSibling:null,void(n.parentNode?.insertBefore(n,r),u(e))}),n.appendChild(d),u(e),r}o.defineInteropFlag(n),o.export(n,"CouplingAddEdit",()=>l),(l||(l={})).doSetUp=function(){let e=document.getElementById("bimdb-cae-vehicles");if(null===e)return;let t=e.parentElement;if(null===t)return;let n=e.form;if(null===n)return;n.addEventListener("submit",r=>(function(e,t,n,r){r.preventDefault();let l=Array.prototype.slice.call(t.querySelectorAll(".vehicle-entry"),0),o=[];for(let e of l){let t=e.querySelector("input.vehicle-number");null!==t&&o.push(t.value)}for(let e of(n.value=o.join("\n"),l))e.parentNode?.removeChild(e);e.submit()})(n,t,e,r));let r=document.createElement("div");for(let n of(r.classList.add("add-vehicle"),t.appendChild(r),e.value.split("\n").map(e=>e.trim()).filter(e=>e.length>0)))d(t,r).value=n;let l=document.createElement("input");l.type="button",l.value="+",l.addEventListener("click",()=>{d(t,r).focus()}),r.appendChild(l),e.style.display="none";let o=document.getElementById("bimdb-cae-company");null!==o&&o.focus()}},{"@parcel/transformer-js/src/esmodule-helpers.js":"8YXJJ"}]},["bvhvx"],"bvhvx","parcelRequire4688",{});
 //# sourceMappingURL=bimdatabase.js.map
@@ -58,6 +58,39 @@ export module AddEdit {
         return [keyInput, valueInput];
     }
 
+    function updateValueSetOptions(companyInput: HTMLInputElement, selects: HTMLSelectElement[]) {
+        const company = companyInput.value.trim();
+        for (let select of selects) {
+            const options: HTMLOptionElement[] = Array.prototype.slice.call(select.options, 0);
+            const optionCompanies: string[][] = options.map(o => JSON.parse(o.dataset.companies ?? "[]"));
+
+            // a company to which values have been assigned may only use those
+            const restricted = optionCompanies.some(cs => cs.indexOf(company) !== -1);
+            let firstOffered: HTMLOptionElement|null = null;
+            let selectionLost = false;
+            for (let i = 0; i < options.length; i++) {
+                const option = options[i];
+                // keep the values stored with the vehicle, which remain valid for it
+                const offered = option.value === ""
+                    || option.dataset.stored !== undefined
+                    || !restricted
+                    || optionCompanies[i].indexOf(company) !== -1;
+                option.hidden = !offered;
+                option.disabled = !offered;
+                if (offered && firstOffered === null) {
+                    firstOffered = option;
+                }
+                if (!offered && option.selected) {
+                    option.selected = false;
+                    selectionLost = true;
+                }
+            }
+            if (selectionLost && !select.multiple && firstOffered !== null) {
+                firstOffered.selected = true;
+            }
+        }
+    }
+
     export function doSetUp() {
         const otherDataTextArea = <HTMLTextAreaElement|null>document.getElementById("bimdb-ae-other-data");
         if (otherDataTextArea === null) {
@@ -101,8 +134,20 @@ export module AddEdit {
 
         otherDataTextArea.style.display = "none";
 
-        // focus company field
+        // offer the values allowed for the company once it has been picked
         const companyInput = <HTMLInputElement|null>document.getElementById("bimdb-ae-company");
+        const valueSetSelects: HTMLSelectElement[] = [];
+        for (let selectId of ["bimdb-ae-veh-class", "bimdb-ae-power-sources", "bimdb-ae-manufacturer", "bimdb-ae-depot"]) {
+            const element = document.getElementById(selectId);
+            if (element instanceof HTMLSelectElement) {
+                valueSetSelects.push(element);
+            }
+        }
+        if (companyInput !== null) {
+            companyInput.addEventListener("change", () => updateValueSetOptions(companyInput, valueSetSelects));
+        }
+
+        // focus company field
         if (companyInput !== null) {
             companyInput.focus();
         }